@@ -2,7 +2,7 @@
 #![no_main]
 #![feature(type_alias_impl_trait)]
 
-use as7331_rs::As7331;
+use as7331_rs::{as7331::MeasurementMode, As7331};
 use esp_backtrace as _;
 use esp_hal::{
     clock::ClockControl, delay::Delay, gpio::IO, i2c::I2C, peripherals::Peripherals, prelude::*,
@@ -42,7 +42,7 @@ fn main() -> ! {
 
     if chip_id == 0x21 {
         let _ = as7331_sensor.set_configuration_mode();
-        let _ = as7331_sensor.init(0, 0, 0x01, 40, 8, 9);
+        let _ = as7331_sensor.init(MeasurementMode::Continuous, 0, 0x01, 40, 8, 9);
         delay.delay_millis(100);
         let _ = as7331_sensor.set_measurement_mode();
     } else {
@@ -1,28 +1,15 @@
+pub use crate::convert::integration_time_ms;
+use crate::convert::{self, temperature_celsius};
 use core::result::Result::{self, Err, Ok};
-use esp_idf_hal::delay::BLOCK;
+use embedded_hal::delay::DelayNs;
+#[cfg(feature = "esp-idf")]
+use esp_idf_hal::delay::{FreeRtos, TickType, BLOCK};
+#[cfg(feature = "esp-idf")]
 use esp_idf_hal::i2c::I2cDriver;
+#[cfg(feature = "esp-idf")]
 use esp_idf_hal::sys::EspError;
 use log::debug;
 
-// Configuration State Registers
-const AS7331_OSR: u8 = 0x00;
-const AS7331_AGEN: u8 = 0x02;
-const AS7331_CREG1: u8 = 0x06;
-const AS7331_CREG2: u8 = 0x07;
-const AS7331_CREG3: u8 = 0x08;
-const AS7331_BREAK: u8 = 0x09;
-const AS7331_EDGES: u8 = 0x0a;
-const AS7331_OPTREG: u8 = 0x0b;
-
-// Measurement State registers
-const AS7331_STATUS: u8 = 0x00;
-const AS7331_TEMP: u8 = 0x01;
-const AS7331_MRES1: u8 = 0x02;
-const AS7331_MRES2: u8 = 0x03;
-const AS7331_MRES3: u8 = 0x04;
-const AS7331_OUTCONV_L: u8 = 0x05;
-const AS7331_OUTCONV_H: u8 = 0x06;
-
 pub const AS7331_CREG1_GAIN_2048: u8 = 0x0;
 pub const AS7331_CREG1_GAIN_1024: u8 = 0x1;
 pub const AS7331_CREG1_GAIN_512: u8 = 0x2;
@@ -77,179 +64,5915 @@ pub const AS7331_OSR_PD_ON: u8 = 1;
 pub const AS7331_OSR_SW_RES_OFF: u8 = 0;
 pub const AS7331_OSR_SW_RES_ON: u8 = 1;
 
+/// Default effective wavelengths of the channels in nm (peak responsivity)
+pub const AS7331_WAVELENGTH_UVA_NM: f32 = 360.0;
+pub const AS7331_WAVELENGTH_UVB_NM: f32 = 300.0;
+pub const AS7331_WAVELENGTH_UVC_NM: f32 = 260.0;
+
 pub const AS7331_OSR_DOS_NOP: u8 = 0;
 pub const AS7331_OSR_DOS_CONFIGURATION: u8 = 2;
 pub const AS7331_OSR_DOS_MEASUREMENT: u8 = 3;
 
-pub struct As7331<'a> {
-    pub i2c: I2cDriver<'a>,
-    pub addr: u8,
+// AGEN device type of the AS7331 (upper nibble of the chip id 0x21)
+const AS7331_DEVICE_TYPE: u8 = 0x2;
+
+// I2C addresses selectable with the A0/A1 pins
+#[cfg(feature = "heapless")]
+const AS7331_ADDRESSES: core::ops::RangeInclusive<u8> = 0x74..=0x77;
+
+// Timeout of the bus probe in bus_healthy
+#[cfg(feature = "esp-idf")]
+const AS7331_PROBE_TIMEOUT_MS: u64 = 10;
+
+// Power-on reset values
+const AS7331_OSR_DEFAULT: u8 = 0x42;
+// CREG1, CREG2, CREG3, BREAK, EDGES, OPTREG
+const AS7331_CONFIG_DEFAULTS: [u8; 6] = [0xa6, 0x40, 0x50, 0x19, 0x01, 0x73];
+
+// Wait after a software reset before the registers are written
+const AS7331_RESET_DELAY_MS: u32 = 100;
+
+// Measurements discarded after reset or wake while the analog front end settles
+const AS7331_WARMUP_SAMPLES: u8 = 1;
+// Time allowed for a warm-up conversion on top of the integration time
+const AS7331_WARMUP_TIMEOUT_MS: u32 = 100;
+
+// Reads covered by the saturation rate unless set otherwise
+const AS7331_SATURATION_WINDOW: u8 = 32;
+
+const AS7331_CREG2_EN_DIV: u8 = 0x08;
+const AS7331_CREG2_DIV_MASK: u8 = 0x0f;
+
+const AS7331_CREG3_MMODE_MASK: u8 = 0xc0;
+const AS7331_CREG3_SB_MASK: u8 = 0x10;
+const AS7331_CREG3_CCLK_MASK: u8 = 0x03;
+
+// Status register bits (second byte of the STATUS read)
+const AS7331_STATUS_POWERSTATE: u8 = 0x01;
+const AS7331_STATUS_STANDBYSTATE: u8 = 0x02;
+const AS7331_STATUS_NOTREADY: u8 = 0x04;
+const AS7331_STATUS_NDATA: u8 = 0x08;
+const AS7331_STATUS_LDATA: u8 = 0x10;
+const AS7331_STATUS_ADCOF: u8 = 0x20;
+const AS7331_STATUS_MRESOF: u8 = 0x40;
+const AS7331_STATUS_OUTCONVOF: u8 = 0x80;
+
+// CIE erythemal action spectrum averaged over the UVA and UVB passbands
+const AS7331_ERYTHEMAL_WEIGHT_UVA: f32 = 0.0018;
+const AS7331_ERYTHEMAL_WEIGHT_UVB: f32 = 0.64;
+
+// 1 uW/cm^2 = 0.01 W/m^2
+const AS7331_UW_CM2_TO_W_M2: f32 = 0.01;
+
+// Minimal erythemal dose of skin type II in J/m^2 (erythemally weighted)
+const AS7331_MED_J_M2: f32 = 250.0;
+
+// Photons per uJ at 1 nm wavelength: 1e-6 J / (h * c / 1 nm)
+const AS7331_PHOTONS_PER_UJ_NM: f32 = 5.034_117e9;
+
+// Typical supply currents in uA
+const AS7331_CURRENT_ACTIVE_UA: f32 = 1500.0;
+const AS7331_CURRENT_STANDBY_UA: f32 = 970.0;
+const AS7331_CURRENT_POWER_DOWN_UA: f32 = 1.0;
+
+// Auto-ranging window for the peak UV count
+const AS7331_AUTORANGE_HIGH: u16 = 0xE000;
+const AS7331_AUTORANGE_LOW: u16 = 0x7000;
+const AS7331_AUTORANGE_MAX_ITERATIONS: usize = 12;
+
+/// Error of the driver, `E` is the error of the [`RegisterAccess`] transport.
+#[derive(Debug)]
+pub enum As7331Error<E> {
+    /// I2C transaction failed
+    I2c(E),
+    /// no new data within the given timeout
+    Timeout,
+    /// a setting is out of range or not usable in the current mode
+    InvalidConfig { field: &'static str, value: u32 },
+    /// AGEN does not identify an AS7331
+    WrongChipId(u8),
+    /// a register read back a different value than was written
+    WriteVerifyFailed { reg: u8, wrote: u8, read: u8 },
+    /// the operation is not possible in the current device state
+    WrongMode,
+    /// the result is clipped at full scale
+    Saturated,
+    /// the raw TEMP value is outside the valid conversion window
+    TemperatureOutOfRange(u16),
+    /// continuous conversions stopped delivering new data
+    Stalled,
 }
 
-#[allow(dead_code)]
-impl<'a> As7331<'a> {
-    pub fn new(i2c: I2cDriver<'a>, addr: u8) -> Self {
-        As7331 { i2c, addr }
+impl<E> From<E> for As7331Error<E> {
+    fn from(e: E) -> Self {
+        As7331Error::I2c(e)
     }
+}
 
-    pub fn destroy(self) -> I2cDriver<'a> {
-        self.i2c
+/// Measurement mode (CREG3 MMODE).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeasurementMode {
+    /// continuous measurement
+    Continuous,
+    /// one-shot measurement started by the SS bit
+    Command,
+    /// measurement started by a SYN edge, fixed integration time
+    SynStart,
+    /// measurement started and ended by SYN edges
+    SynStartEnd,
+}
+
+impl MeasurementMode {
+    /// Register value of the MMODE field.
+    pub fn bits(self) -> u8 {
+        match self {
+            MeasurementMode::Continuous => AS7331_CREG3_MMODE_CONT,
+            MeasurementMode::Command => AS7331_CREG3_MMODE_CMD,
+            MeasurementMode::SynStart => AS7331_CREG3_MMODE_SYNS,
+            MeasurementMode::SynStartEnd => AS7331_CREG3_MMODE_SYND,
+        }
     }
 
-    pub fn get_chip_id(&mut self) -> Result<u8, EspError> {
-        let mut data = [0u8; 1];
-        self.i2c_write_read_cmd(AS7331_AGEN, &mut data)?;
-        Ok(data[0])
+    /// Decodes the two MMODE bits.
+    pub fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            AS7331_CREG3_MMODE_CONT => MeasurementMode::Continuous,
+            AS7331_CREG3_MMODE_CMD => MeasurementMode::Command,
+            AS7331_CREG3_MMODE_SYNS => MeasurementMode::SynStart,
+            _ => MeasurementMode::SynStartEnd,
+        }
     }
+}
 
-    pub fn init(
-        &mut self,
-        mmode: u8,
-        cclk: u8,
-        sb: u8,
-        break_time: u8,
-        gain: u8,
-        time: u8,
-    ) -> Result<(), EspError> {
-        self.i2c_write_cmd(AS7331_CREG1, gain << 4 | time)?;
-        self.i2c_write_cmd(AS7331_CREG3, mmode << 6 | sb << 4 | cclk)?;
-        self.i2c_write_cmd(AS7331_BREAK, break_time)
+/// Gain of the ADC (CREG1 GAIN).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Gain {
+    X1,
+    X2,
+    X4,
+    X8,
+    X16,
+    X32,
+    X64,
+    X128,
+    X256,
+    X512,
+    X1024,
+    X2048,
+}
+
+impl Gain {
+    /// Register value of the GAIN field.
+    pub fn bits(self) -> u8 {
+        match self {
+            Gain::X1 => AS7331_CREG1_GAIN_1,
+            Gain::X2 => AS7331_CREG1_GAIN_2,
+            Gain::X4 => AS7331_CREG1_GAIN_4,
+            Gain::X8 => AS7331_CREG1_GAIN_8,
+            Gain::X16 => AS7331_CREG1_GAIN_16,
+            Gain::X32 => AS7331_CREG1_GAIN_32,
+            Gain::X64 => AS7331_CREG1_GAIN_64,
+            Gain::X128 => AS7331_CREG1_GAIN_128,
+            Gain::X256 => AS7331_CREG1_GAIN_256,
+            Gain::X512 => AS7331_CREG1_GAIN_512,
+            Gain::X1024 => AS7331_CREG1_GAIN_1024,
+            Gain::X2048 => AS7331_CREG1_GAIN_2048,
+        }
     }
 
-    pub fn one_shot(&mut self) -> Result<(), EspError> {
-        let mut data = [0u8; 1];
-        self.i2c_write_read_cmd(AS7331_OSR, &mut data)?;
-        self.i2c_write_cmd(AS7331_OSR, data[0] | 0x80)
+    /// Amplification factor, `2^(11 - bits)`.
+    pub fn factor(self) -> u32 {
+        1 << (11 - self.bits())
     }
+}
 
-    pub fn get_status(&mut self) -> Result<[u8; 8], EspError> {
-        let mut data = [0u8; 2];
-        self.i2c_read_bytes(AS7331_STATUS, &mut data)?;
-        Ok([
-            (data[1] & 0x01) >> 0,
-            (data[1] & 0x02) >> 1,
-            (data[1] & 0x04) >> 2,
-            (data[1] & 0x08) >> 3,
-            (data[1] & 0x10) >> 4,
-            (data[1] & 0x20) >> 5,
-            (data[1] & 0x40) >> 6,
-            (data[1] & 0x80) >> 7,
-        ])
+/// Integration time at CCLK = 1.024 MHz (CREG1 TIME).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IntegrationTime {
+    Ms1,
+    Ms2,
+    Ms4,
+    Ms8,
+    Ms16,
+    Ms32,
+    Ms64,
+    Ms128,
+    Ms256,
+    Ms512,
+    Ms1024,
+    Ms2048,
+    Ms4096,
+    Ms8192,
+    Ms16384,
+}
+
+impl IntegrationTime {
+    /// Register value of the TIME field.
+    pub fn bits(self) -> u8 {
+        match self {
+            IntegrationTime::Ms1 => AS7331_CREG1_TIME_1,
+            IntegrationTime::Ms2 => AS7331_CREG1_TIME_2,
+            IntegrationTime::Ms4 => AS7331_CREG1_TIME_4,
+            IntegrationTime::Ms8 => AS7331_CREG1_TIME_8,
+            IntegrationTime::Ms16 => AS7331_CREG1_TIME_16,
+            IntegrationTime::Ms32 => AS7331_CREG1_TIME_32,
+            IntegrationTime::Ms64 => AS7331_CREG1_TIME_64,
+            IntegrationTime::Ms128 => AS7331_CREG1_TIME_128,
+            IntegrationTime::Ms256 => AS7331_CREG1_TIME_256,
+            IntegrationTime::Ms512 => AS7331_CREG1_TIME_512,
+            IntegrationTime::Ms1024 => AS7331_CREG1_TIME_1024,
+            IntegrationTime::Ms2048 => AS7331_CREG1_TIME_2048,
+            IntegrationTime::Ms4096 => AS7331_CREG1_TIME_4096,
+            IntegrationTime::Ms8192 => AS7331_CREG1_TIME_8192,
+            IntegrationTime::Ms16384 => AS7331_CREG1_TIME_16384,
+        }
     }
 
-    pub fn read_temp_data(&mut self) -> Result<u16, EspError> {
-        let mut data = [0u8; 2];
-        self.i2c_read_bytes(AS7331_TEMP, &mut data)?;
-        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+    /// Integration time in ms at CCLK = 1.024 MHz.
+    pub fn ms(self) -> u32 {
+        1 << self.bits()
     }
+}
 
-    pub fn read_uv_a_data(&mut self) -> Result<u16, EspError> {
-        let mut data = [0u8; 2];
-        self.i2c_read_bytes(AS7331_MRES1, &mut data)?;
-        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+/// Conversion clock (CREG3 CCLK).
+///
+/// A faster clock shortens the conversion by the same factor, at the cost of
+/// a lower count for the same TIME setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Cclk {
+    Mhz1_024,
+    Mhz2_048,
+    Mhz4_096,
+    Mhz8_192,
+}
+
+impl Cclk {
+    /// Register value of the CCLK field.
+    pub fn bits(self) -> u8 {
+        match self {
+            Cclk::Mhz1_024 => AS7331_CREG3_CCLK_1024,
+            Cclk::Mhz2_048 => AS7331_CREG3_CCLK_2048,
+            Cclk::Mhz4_096 => AS7331_CREG3_CCLK_4096,
+            Cclk::Mhz8_192 => AS7331_CREG3_CCLK_8192,
+        }
     }
 
-    pub fn read_uv_b_data(&mut self) -> Result<u16, EspError> {
-        let mut data = [0u8; 2];
-        self.i2c_read_bytes(AS7331_MRES2, &mut data)?;
-        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+    /// Clock frequency in kHz.
+    pub fn khz(self) -> u32 {
+        1024 << self.bits()
     }
+}
 
-    pub fn read_uv_c_data(&mut self) -> Result<u16, EspError> {
-        let mut data = [0u8; 2];
-        self.i2c_read_bytes(AS7331_MRES3, &mut data)?;
-        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+/// All gains in ascending order, e.g. to fill a menu.
+pub const fn available_gains() -> &'static [Gain] {
+    &[
+        Gain::X1,
+        Gain::X2,
+        Gain::X4,
+        Gain::X8,
+        Gain::X16,
+        Gain::X32,
+        Gain::X64,
+        Gain::X128,
+        Gain::X256,
+        Gain::X512,
+        Gain::X1024,
+        Gain::X2048,
+    ]
+}
+
+/// All integration times in ascending order.
+pub const fn available_integration_times() -> &'static [IntegrationTime] {
+    &[
+        IntegrationTime::Ms1,
+        IntegrationTime::Ms2,
+        IntegrationTime::Ms4,
+        IntegrationTime::Ms8,
+        IntegrationTime::Ms16,
+        IntegrationTime::Ms32,
+        IntegrationTime::Ms64,
+        IntegrationTime::Ms128,
+        IntegrationTime::Ms256,
+        IntegrationTime::Ms512,
+        IntegrationTime::Ms1024,
+        IntegrationTime::Ms2048,
+        IntegrationTime::Ms4096,
+        IntegrationTime::Ms8192,
+        IntegrationTime::Ms16384,
+    ]
+}
+
+/// UV measurement channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Uva,
+    Uvb,
+    Uvc,
+}
+
+impl Channel {
+    fn index(self) -> usize {
+        match self {
+            Channel::Uva => 0,
+            Channel::Uvb => 1,
+            Channel::Uvc => 2,
+        }
     }
+}
 
-    pub fn read_all_data(&mut self) -> Result<[u16; 4], EspError> {
-        let mut raw_data = [0u8; 8];
-        self.i2c_read_bytes(AS7331_TEMP, &mut raw_data)?;
-        Ok([
-            ((raw_data[1] as u16) << 8) | (raw_data[0] as u16),
-            ((raw_data[3] as u16) << 8) | (raw_data[2] as u16),
-            ((raw_data[5] as u16) << 8) | (raw_data[4] as u16),
-            ((raw_data[7] as u16) << 8) | (raw_data[6] as u16),
-        ])
+/// Byte order of 16-bit values, see [`As7331::read_all_data_bytes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// Register of the configuration state, with its address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ConfigReg {
+    Osr = 0x00,
+    Agen = 0x02,
+    Creg1 = 0x06,
+    Creg2 = 0x07,
+    Creg3 = 0x08,
+    Break = 0x09,
+    Edges = 0x0a,
+    Optreg = 0x0b,
+}
+
+impl ConfigReg {
+    pub fn addr(self) -> u8 {
+        self as u8
     }
+}
 
-    fn i2c_write_read_cmd(&mut self, addr: u8, data: &mut [u8]) -> Result<(), EspError> {
-        match self.i2c.write_read(self.addr, &[addr], data, BLOCK) {
-            Ok(_) => debug!(
-                "I2C_WRITE_READ - ADDR: 0x{:02X} - READ: 0x{:02X}",
-                addr, data[0]
-            ),
-            Err(e) => return Err(e),
+/// Register of the measurement state, with its address.
+///
+/// The address selects the register of the current device state, so
+/// [`ConfigReg`] and [`MeasReg`] share addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MeasReg {
+    Status = 0x00,
+    Temp = 0x01,
+    Mres1 = 0x02,
+    Mres2 = 0x03,
+    Mres3 = 0x04,
+    OutconvL = 0x05,
+    OutconvH = 0x06,
+}
+
+impl MeasReg {
+    pub fn addr(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Register of either state, for the internal transfer helpers.
+trait Register: Copy {
+    fn addr(self) -> u8;
+}
+
+impl Register for ConfigReg {
+    fn addr(self) -> u8 {
+        ConfigReg::addr(self)
+    }
+}
+
+impl Register for MeasReg {
+    fn addr(self) -> u8 {
+        MeasReg::addr(self)
+    }
+}
+
+// Result registers in the order of the result block
+const AS7331_RESULT_REGS: [MeasReg; 4] = [
+    MeasReg::Temp,
+    MeasReg::Mres1,
+    MeasReg::Mres2,
+    MeasReg::Mres3,
+];
+
+// Burst reads starting at STATUS or TEMP index the block by register offset,
+// so the measurement registers have to follow each other in this order.
+const _: () = {
+    let order = [
+        MeasReg::Status,
+        MeasReg::Temp,
+        MeasReg::Mres1,
+        MeasReg::Mres2,
+        MeasReg::Mres3,
+        MeasReg::OutconvL,
+        MeasReg::OutconvH,
+    ];
+    let mut i = 1;
+    while i < order.len() {
+        assert!(order[i] as u8 == order[i - 1] as u8 + 1);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < AS7331_RESULT_REGS.len() {
+        assert!(AS7331_RESULT_REGS[i] as u8 == MeasReg::Temp as u8 + i as u8);
+        i += 1;
+    }
+};
+
+/// Decoded STATUS register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Status {
+    /// device is powered down
+    pub power_state: bool,
+    /// device is in standby
+    pub standby_state: bool,
+    /// a conversion is running
+    pub not_ready: bool,
+    /// new results are available
+    pub ndata: bool,
+    /// results were overwritten before they were read
+    pub ldata: bool,
+    pub adc_overflow: bool,
+    pub mres_overflow: bool,
+    pub outconv_overflow: bool,
+}
+
+impl Status {
+    pub fn from_byte(status: u8) -> Self {
+        Status {
+            power_state: status & AS7331_STATUS_POWERSTATE != 0,
+            standby_state: status & AS7331_STATUS_STANDBYSTATE != 0,
+            not_ready: status & AS7331_STATUS_NOTREADY != 0,
+            ndata: status & AS7331_STATUS_NDATA != 0,
+            ldata: status & AS7331_STATUS_LDATA != 0,
+            adc_overflow: status & AS7331_STATUS_ADCOF != 0,
+            mres_overflow: status & AS7331_STATUS_MRESOF != 0,
+            outconv_overflow: status & AS7331_STATUS_OUTCONVOF != 0,
         }
-        Ok(())
     }
+}
 
-    fn i2c_read_bytes(&mut self, addr: u8, data: &mut [u8]) -> Result<(), EspError> {
-        match self.i2c.write_read(self.addr, &[addr], data, BLOCK) {
-            Ok(_) => debug!("I2C_READ_BYTES - ADDR: 0x{:02X} - DATA {:?}", addr, data),
-            Err(e) => return Err(e),
+/// Decoded OSR register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Osr {
+    /// device operating state (`AS7331_OSR_DOS_*`)
+    pub dos: u8,
+    /// software reset
+    pub sw_res: bool,
+    /// power down
+    pub pd: bool,
+    /// start state, starts a measurement in CMD and SYNS mode
+    pub ss: bool,
+}
+
+impl Osr {
+    pub fn from_byte(osr: u8) -> Self {
+        Osr {
+            dos: osr & 0x07,
+            sw_res: osr & (AS7331_OSR_SW_RES_ON << 3) != 0,
+            pd: osr & (AS7331_OSR_PD_ON << 6) != 0,
+            ss: osr & (AS7331_OSR_SS_MEASUREMENT << 7) != 0,
         }
-        Ok(())
     }
 
-    fn i2c_write_cmd(&mut self, addr: u8, cmd: u8) -> Result<(), EspError> {
-        match self.i2c.write(self.addr, &[addr, cmd], BLOCK) {
-            Ok(_) => debug!("I2C_WRITE - ADDR: 0x{:02X} - DATa: 0x{:02X}", addr, cmd),
-            Err(e) => return Err(e),
+    pub fn to_byte(self) -> u8 {
+        (self.ss as u8) << 7 | (self.pd as u8) << 6 | (self.sw_res as u8) << 3 | (self.dos & 0x07)
+    }
+}
+
+/// Result of [`As7331::health_check`].
+///
+/// AGEN can only be read in configuration mode, STATUS and TEMP only in
+/// measurement mode, so the fields of the other state are `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthReport {
+    /// operating state and power down bit
+    pub osr: Osr,
+    pub chip_id: Option<u8>,
+    /// whether AGEN identifies an AS7331
+    pub chip_id_valid: Option<bool>,
+    /// standby state and overflow flags
+    pub status: Option<Status>,
+    /// temperature of the last result in Celsius
+    pub temperature: Option<f32>,
+}
+
+/// Which overflow flag of the status register is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowReason {
+    /// ADCOF: an ADC input overflowed during integration, lower the gain
+    Adc,
+    /// MRESOF: a result exceeded 16 bits, shorten the integration time
+    Mres,
+    /// OUTCONVOF: the OUTCONV counter exceeded 24 bits
+    Conversion,
+}
+
+/// Gain advice of [`As7331::read_with_range_hint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeHint {
+    /// the reading is inside the auto-ranging window
+    Ok,
+    /// the strongest channel is below 7/16 of the full-scale count
+    IncreaseGain,
+    /// a channel is close to saturation or overflowed
+    DecreaseGain,
+}
+
+/// Outcome of [`As7331::is_dark_reading`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DarkReading {
+    /// all channels at 0 counts from a completed conversion
+    Dark,
+    /// all channels at 0 counts without a completed, valid conversion, e.g.
+    /// reset values of a device that is not measuring
+    NotMeasuring,
+    /// at least one channel above 0 counts
+    Lit,
+}
+
+/// Contents of the AGEN register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// DEVID, upper nibble (0x2 for the AS7331)
+    pub device_type: u8,
+    /// MUT, lower nibble
+    pub mutation: u8,
+}
+
+impl DeviceInfo {
+    pub fn from_agen(agen: u8) -> Self {
+        DeviceInfo {
+            device_type: agen >> 4,
+            mutation: agen & 0x0f,
+        }
+    }
+}
+
+/// Digital divider of the results (CREG2 EN_DIV and DIV).
+///
+/// With the divider active every result is divided by the factor before it
+/// is stored, which extends the range at long integration times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Divider {
+    Off,
+    Div2,
+    Div4,
+    Div8,
+    Div16,
+    Div32,
+    Div64,
+    Div128,
+    Div256,
+}
+
+impl Divider {
+    /// Decodes the EN_DIV and DIV bits of a CREG2 value.
+    pub fn from_creg2(creg2: u8) -> Self {
+        if creg2 & AS7331_CREG2_EN_DIV == 0 {
+            return Divider::Off;
+        }
+        match creg2 & 0x07 {
+            0 => Divider::Div2,
+            1 => Divider::Div4,
+            2 => Divider::Div8,
+            3 => Divider::Div16,
+            4 => Divider::Div32,
+            5 => Divider::Div64,
+            6 => Divider::Div128,
+            _ => Divider::Div256,
+        }
+    }
+
+    /// EN_DIV and DIV bits for CREG2.
+    pub fn bits(self) -> u8 {
+        match self {
+            Divider::Off => 0,
+            d => AS7331_CREG2_EN_DIV | (d.shift() - 1),
+        }
+    }
+
+    /// Division factor, 1 when off.
+    pub fn factor(self) -> u32 {
+        1 << self.shift()
+    }
+
+    pub(crate) fn shift(self) -> u8 {
+        match self {
+            Divider::Off => 0,
+            Divider::Div2 => 1,
+            Divider::Div4 => 2,
+            Divider::Div8 => 3,
+            Divider::Div16 => 4,
+            Divider::Div32 => 5,
+            Divider::Div64 => 6,
+            Divider::Div128 => 7,
+            Divider::Div256 => 8,
+        }
+    }
+}
+
+impl Config {
+    /// Checks field ranges and combinations the device cannot use.
+    ///
+    /// Rejects out-of-range register codes, SYND mode with EDGES=0 (the
+    /// measurement would never end) and a zero break in CONT or SYND mode
+    /// (see [`As7331::set_break_time`]).
+    pub fn validate<E>(&self) -> Result<(), As7331Error<E>> {
+        let invalid = |field, value: u8| {
+            Err(As7331Error::InvalidConfig {
+                field,
+                value: value as u32,
+            })
+        };
+        if self.gain > AS7331_CREG1_GAIN_1 {
+            return invalid("gain", self.gain);
+        }
+        if self.time > AS7331_CREG1_TIME_16384 {
+            return invalid("time", self.time);
+        }
+        if self.mmode > AS7331_CREG3_MMODE_SYND {
+            return invalid("mmode", self.mmode);
+        }
+        if self.cclk > AS7331_CREG3_CCLK_8192 {
+            return invalid("cclk", self.cclk);
+        }
+        if self.sb > AS7331_CREG3_SB_ON {
+            return invalid("sb", self.sb);
+        }
+        if self.mmode == AS7331_CREG3_MMODE_SYND && self.edges == 0 {
+            return invalid("edges", self.edges);
+        }
+        let back_to_back = matches!(
+            self.mmode,
+            AS7331_CREG3_MMODE_CONT | AS7331_CREG3_MMODE_SYND
+        );
+        if back_to_back && self.break_time == 0 {
+            return invalid("break_time", self.break_time);
         }
         Ok(())
     }
+}
+
+/// Per-channel correction applied to the irradiance of UVA, UVB, UVC.
+///
+/// The corrected irradiance is `E * scale / (1 + tc * (T - T_ref))` with `tc`
+/// the temperature coefficient, `T` the die temperature of the same
+/// measurement and `T_ref` the reference temperature. The default is no
+/// correction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// calibration factor at the reference temperature
+    pub scale: [f32; 3],
+    /// relative change of the responsivity per Celsius
+    pub temp_coefficient: [f32; 3],
+    /// temperature in Celsius at which `scale` applies
+    pub reference_temperature: f32,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            scale: [1.0; 3],
+            temp_coefficient: [0.0; 3],
+            reference_temperature: 25.0,
+        }
+    }
+}
 
-    pub fn power_up(&mut self) -> Result<(), EspError> {
-        let mut data = [0u8; 22];
-        //self.i2c_write_read_cmd(AS7331_OSR, &mut data)?;
-        self.i2c_write_cmd(AS7331_OSR, data[0] | 0x40)
+impl Calibration {
+    /// Serializes to a version byte followed by scale, temp_coefficient and
+    /// reference_temperature as little-endian `f32`.
+    pub fn to_bytes(&self) -> [u8; CALIBRATION_BLOB_LEN] {
+        let mut out = [0u8; CALIBRATION_BLOB_LEN];
+        out[0] = CALIBRATION_BLOB_VERSION;
+        let values = self
+            .scale
+            .iter()
+            .chain(self.temp_coefficient.iter())
+            .chain(core::iter::once(&self.reference_temperature));
+        for (chunk, v) in out[1..].chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&v.to_le_bytes());
+        }
+        out
     }
 
-    pub fn power_down(&mut self) -> Result<(), EspError> {
-        let mut data = [0u8; 22];
-        //self.i2c_write_read_cmd(AS7331_OSR, &mut data)?;
+    /// Restores a calibration written by [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Calibration, CalibrationError> {
+        if bytes.len() != CALIBRATION_BLOB_LEN {
+            return Err(CalibrationError::WrongLength(bytes.len()));
+        }
+        if bytes[0] != CALIBRATION_BLOB_VERSION {
+            return Err(CalibrationError::UnsupportedVersion(bytes[0]));
+        }
+        let mut values = [0f32; 7];
+        for (v, chunk) in values.iter_mut().zip(bytes[1..].chunks_exact(4)) {
+            *v = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        Ok(Calibration {
+            scale: [values[0], values[1], values[2]],
+            temp_coefficient: [values[3], values[4], values[5]],
+            reference_temperature: values[6],
+        })
+    }
 
-        self.i2c_write_cmd(AS7331_OSR, data[0] & !0x40)
+    /// Factors for UVA, UVB and UVC at `temperature`; without a temperature
+    /// only `scale` is applied.
+    fn factors(&self, temperature: Option<f32>) -> [f32; 3] {
+        let mut f = self.scale;
+        if let Some(t) = temperature {
+            let dt = t - self.reference_temperature;
+            for (f, tc) in f.iter_mut().zip(self.temp_coefficient) {
+                *f /= 1.0 + tc * dt;
+            }
+        }
+        f
     }
+}
 
-    pub fn reset(&mut self) -> Result<(), EspError> {
-        let mut data = [0u8; 22];
-        /*match self.i2c_write_read_cmd(AS7331_OSR, &mut data) {
-            Err(e) => return Err(e),
-            _ => {}
-        }*/
+/// Version of the [`Calibration::to_bytes`] layout.
+pub const CALIBRATION_BLOB_VERSION: u8 = 1;
+/// Length of the [`Calibration::to_bytes`] blob.
+pub const CALIBRATION_BLOB_LEN: usize = 29;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// blob is not [`CALIBRATION_BLOB_LEN`] bytes long
+    WrongLength(usize),
+    /// blob was written by an unknown layout version
+    UnsupportedVersion(u8),
+}
+
+/// Error of [`Config::from_register_str`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// an entry is not of the form `NAME=VALUE`
+    Syntax,
+    /// the name is not one of CREG1, CREG2, CREG3, BREAK or EDGES
+    UnknownRegister,
+    /// the value is not a decimal or `0x` hexadecimal byte
+    InvalidValue,
+}
 
-        self.i2c_write_cmd(AS7331_OSR, data[0] | 0x08)
+/// Register settings as written by [`As7331::apply`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub mmode: u8,
+    pub cclk: u8,
+    pub sb: u8,
+    pub break_time: u8,
+    pub gain: u8,
+    pub time: u8,
+    pub divider: Divider,
+    /// number of SYN edges ending a SYND measurement (EDGES register)
+    pub edges: u8,
+}
+
+impl Default for Config {
+    /// Power-on reset values of CREG1, CREG2, CREG3, BREAK and EDGES.
+    fn default() -> Self {
+        Config {
+            mmode: AS7331_CREG3_MMODE_CMD,
+            cclk: AS7331_CREG3_CCLK_1024,
+            sb: AS7331_CREG3_SB_ON,
+            break_time: 0x19,
+            gain: AS7331_CREG1_GAIN_2,
+            time: AS7331_CREG1_TIME_64,
+            divider: Divider::Off,
+            edges: 0x01,
+        }
     }
+}
 
-    pub fn set_configuration_mode(&mut self) -> Result<(), EspError> {
-        let mut data = [0u8; 22];
-        /*match self.i2c_write_read_cmd(AS7331_OSR, &mut data) {
-            Err(e) => return Err(e),
-            _ => {}
-        }*/
+impl Config {
+    /// Baseline used by [`As7331::reset_to_defaults`]: command mode, gain 16,
+    /// 64 ms integration time, 1.024 MHz clock and no divider; the other
+    /// fields keep their reset values.
+    pub fn baseline() -> Self {
+        Config {
+            gain: AS7331_CREG1_GAIN_16,
+            time: AS7331_CREG1_TIME_64,
+            ..Config::default()
+        }
+    }
 
-        self.i2c_write_cmd(AS7331_OSR, data[0] | 0x02)
+    /// Pause between measurements in us, see [`convert::break_time_us`].
+    pub fn break_time_us(&self) -> u32 {
+        convert::break_time_us(self.break_time, self.cclk)
     }
 
-    pub fn get_mode(&mut self) -> Result<[u8; 4], EspError> {
-        let mut raw_data = [0u8; 2];
-        self.i2c_read_bytes(AS7331_OSR, &mut raw_data)?;
-        Ok([
-            (raw_data[0] & 0x07),
-            (raw_data[0] & 0x08) >> 3,
-            (raw_data[0] & 0x40) >> 6,
-            (raw_data[0] & 0x80) >> 7,
-        ])
+    /// Parses a register dump such as `CREG1=0x76 CREG3=0x40 BREAK=0x10`.
+    ///
+    /// Entries are separated by whitespace, names are case-insensitive and
+    /// values are decimal or `0x` hexadecimal bytes. Registers not listed
+    /// keep their reset values. The result is not checked; [`As7331::apply`]
+    /// validates it before writing.
+    pub fn from_register_str(s: &str) -> Result<Config, ParseError> {
+        let mut regs = [0u8; 5];
+        regs.copy_from_slice(&AS7331_CONFIG_DEFAULTS[..5]);
+        for entry in s.split_whitespace() {
+            let (name, value) = entry.split_once('=').ok_or(ParseError::Syntax)?;
+            let index = ["CREG1", "CREG2", "CREG3", "BREAK", "EDGES"]
+                .iter()
+                .position(|reg| reg.eq_ignore_ascii_case(name))
+                .ok_or(ParseError::UnknownRegister)?;
+            let value = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => value.parse(),
+            };
+            regs[index] = value.map_err(|_| ParseError::InvalidValue)?;
+        }
+        Ok(Config::from_registers(regs))
     }
 
-    pub fn set_measurement_mode(&mut self) -> Result<(), EspError> {
-        let mut data = [0u8; 22];
-        /*match self.i2c_write_read_cmd(AS7331_OSR, &mut data) {
-            Err(e) => return Err(e),
-            _ => {}
-        }*/
+    /// Decodes CREG1, CREG2, CREG3, BREAK and EDGES.
+    fn from_registers(regs: [u8; 5]) -> Config {
+        let (gain, time) = unpack_creg1(regs[0]);
+        Config {
+            mmode: regs[2] >> 6,
+            cclk: regs[2] & 0x03,
+            sb: (regs[2] >> 4) & 0x01,
+            break_time: regs[3],
+            gain,
+            time,
+            divider: Divider::from_creg2(regs[1]),
+            edges: regs[4],
+        }
+    }
+}
+
+/// One decoded result block.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Measurement {
+    /// raw counts in register order: temperature, UVA, UVB, UVC
+    pub raw: [u16; 4],
+    /// die temperature in Celsius
+    pub temperature: f32,
+    /// irradiance in uW/cm^2
+    pub uva: f32,
+    pub uvb: f32,
+    pub uvc: f32,
+}
+
+/// Result of [`As7331::measure_uv`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvMeasurement {
+    /// raw counts of UVA, UVB, UVC
+    pub raw: [u16; 3],
+    /// die temperature in Celsius, `None` if the temperature read failed
+    pub temperature: Option<f32>,
+    /// irradiance in uW/cm^2
+    pub uva: f32,
+    pub uvb: f32,
+    pub uvc: f32,
+}
+
+/// Result of [`As7331::read_detailed`], every value with its own outcome.
+#[derive(Debug)]
+pub struct DetailedReading<E> {
+    /// die temperature in Celsius
+    pub temperature: Result<f32, As7331Error<E>>,
+    /// irradiance in uW/cm^2
+    pub uva: Result<f32, As7331Error<E>>,
+    pub uvb: Result<f32, As7331Error<E>>,
+    pub uvc: Result<f32, As7331Error<E>>,
+}
+
+/// One-shot conversion started by [`As7331::trigger`], to be collected with
+/// [`As7331::try_complete`].
+#[derive(Debug)]
+#[must_use]
+pub struct PendingMeasurement {
+    _private: (),
+}
+
+/// Result registers read by [`As7331::measure_blocking`] and [`As7331::read`].
+///
+/// This is a driver setting, the device always converts all channels.
+/// Channels that are not selected read as 0 counts; without
+/// [`TEMP`](Self::TEMP) the temperature is NaN and no temperature
+/// compensation is applied. The default is [`ALL`](Self::ALL).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadSelection(u8);
+
+impl ReadSelection {
+    pub const NONE: ReadSelection = ReadSelection(0);
+    pub const TEMP: ReadSelection = ReadSelection(0x01);
+    pub const UVA: ReadSelection = ReadSelection(0x02);
+    pub const UVB: ReadSelection = ReadSelection(0x04);
+    pub const UVC: ReadSelection = ReadSelection(0x08);
+    pub const ALL: ReadSelection = ReadSelection(0x0f);
+
+    /// Bit `i` selects entry `i` of the raw block: temperature, UVA, UVB, UVC.
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, other: ReadSelection) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for ReadSelection {
+    fn default() -> Self {
+        ReadSelection::ALL
+    }
+}
+
+impl core::ops::BitOr for ReadSelection {
+    type Output = ReadSelection;
+
+    fn bitor(self, rhs: ReadSelection) -> ReadSelection {
+        ReadSelection(self.0 | rhs.0)
+    }
+}
+
+/// Result of [`As7331::read`] for the current measurement mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadResult {
+    /// CONT, CMD or SYNS mode
+    Measurement(Measurement),
+    /// SYND mode: the result plus the number of conversion clocks (OUTCONV)
+    /// counted during the measurement
+    Synd {
+        measurement: Measurement,
+        conversions: u32,
+    },
+}
+
+/// Raw result block plus the settings needed to convert it elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawRecord {
+    /// raw counts in register order: temperature, UVA, UVB, UVC
+    pub raw: [u16; 4],
+    /// CREG1 GAIN code
+    pub gain: u8,
+    /// CREG1 TIME code
+    pub time: u8,
+    /// CREG2 EN_DIV and DIV bits, see [`Divider::from_creg2`]
+    pub divider: u8,
+    /// CREG3 CCLK code
+    pub cclk: u8,
+}
+
+/// Result of [`As7331::read_si`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SiReading {
+    pub temperature_c: f32,
+    pub uva_w_m2: f32,
+    pub uvb_w_m2: f32,
+    pub uvc_w_m2: f32,
+}
+
+/// Result of [`As7331::read_measurement_uom`].
+#[cfg(feature = "uom")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UomMeasurement {
+    pub temperature: uom::si::f32::ThermodynamicTemperature,
+    /// irradiance, in uom the quantity of power per area
+    pub uva: uom::si::f32::HeatFluxDensity,
+    pub uvb: uom::si::f32::HeatFluxDensity,
+    pub uvc: uom::si::f32::HeatFluxDensity,
+}
+
+/// Ratios between the calibrated irradiance of the channels, see
+/// [`As7331::channel_ratios`].
+///
+/// A ratio is `None` if its denominator is zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelRatios {
+    pub uvb_over_uva: Option<f32>,
+    pub uvc_over_uva: Option<f32>,
+    pub uvc_over_uvb: Option<f32>,
+}
+
+impl ChannelRatios {
+    pub fn from_measurement(m: &Measurement) -> Self {
+        let ratio = |num: f32, den: f32| (den != 0.0).then(|| num / den);
+        ChannelRatios {
+            uvb_over_uva: ratio(m.uvb, m.uva),
+            uvc_over_uva: ratio(m.uvc, m.uva),
+            uvc_over_uvb: ratio(m.uvc, m.uvb),
+        }
+    }
+}
+
+/// Result of [`As7331::spectrum_report`], all derived from one result block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectrumReport {
+    /// raw counts, temperature and calibrated irradiance in uW/cm^2
+    pub measurement: Measurement,
+    /// photon flux in photons/(cm^2 s) for UVA, UVB, UVC
+    pub photon_flux: [f32; 3],
+    pub ratios: ChannelRatios,
+    /// stored config the result was converted with
+    pub config: Config,
+}
+
+/// Result of [`As7331::read_scaled_fixed`], computed without floating point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScaledReading {
+    /// die temperature in milli-Celsius
+    pub temperature_mc: i32,
+    /// irradiance in nW/cm^2, saturating at `u32::MAX`
+    pub uva_nw: u32,
+    pub uvb_nw: u32,
+    pub uvc_nw: u32,
+}
+
+/// Noise of the UVA, UVB and UVC results used by
+/// [`As7331::measurement_uncertainty`]. The default is no noise.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NoiseModel {
+    /// signal-independent noise in counts (e.g. dark noise)
+    pub counts: [f32; 3],
+    /// noise as a fraction of the reading
+    pub relative: [f32; 3],
+}
+
+/// Exponential moving average of the UVA, UVB and UVC irradiance.
+///
+/// Each update computes `y += alpha * (x - y)`; the first measurement
+/// initializes the average. With a sample period `T` the time constant is
+/// roughly `T / alpha`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmaSmoother {
+    pub alpha: f32,
+    value: Option<[f32; 3]>,
+}
+
+impl EmaSmoother {
+    /// `alpha` is clamped to `0.0..=1.0`; 1.0 disables smoothing.
+    pub fn new(alpha: f32) -> Self {
+        EmaSmoother {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// Feeds one measurement and returns the smoothed irradiance.
+    pub fn update(&mut self, m: &Measurement) -> [f32; 3] {
+        let x = [m.uva, m.uvb, m.uvc];
+        let y = match self.value {
+            Some(mut y) => {
+                for (y, x) in y.iter_mut().zip(x) {
+                    *y += self.alpha * (x - *y);
+                }
+                y
+            }
+            None => x,
+        };
+        self.value = Some(y);
+        y
+    }
+
+    /// Current smoothed irradiance, `None` before the first update.
+    pub fn value(&self) -> Option<[f32; 3]> {
+        self.value
+    }
+
+    pub fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+/// Share of saturated reads among the last `window` reads, at most 64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaturationTracker {
+    /// one bit per read, most recent in bit 0
+    history: u64,
+    len: u8,
+    window: u8,
+}
+
+impl SaturationTracker {
+    /// `window` is clamped to `1..=64`.
+    pub fn new(window: u8) -> Self {
+        SaturationTracker {
+            history: 0,
+            len: 0,
+            window: window.clamp(1, 64),
+        }
+    }
+
+    pub fn record(&mut self, saturated: bool) {
+        self.history = self.history << 1 | saturated as u64;
+        self.len = (self.len + 1).min(self.window);
+    }
+
+    /// Fraction of the recorded reads in the window that saturated, 0.0
+    /// before the first read.
+    pub fn saturation_rate(&self) -> f32 {
+        if self.len == 0 {
+            return 0.0;
+        }
+        let mask = u64::MAX >> (64 - self.len as u32);
+        (self.history & mask).count_ones() as f32 / self.len as f32
+    }
+
+    pub fn reset(&mut self) {
+        self.history = 0;
+        self.len = 0;
+    }
+}
+
+impl Default for SaturationTracker {
+    fn default() -> Self {
+        SaturationTracker::new(AS7331_SATURATION_WINDOW)
+    }
+}
+
+/// UV index from UVA/UVB irradiance in uW/cm^2.
+///
+/// One UV index unit is 2.5 uW/cm^2 of erythemally weighted irradiance. The
+/// weighting uses one average factor per channel, so the result is an
+/// approximation that is best for broadband sources such as sunlight.
+pub fn uv_index_from_irradiance(uva: f32, uvb: f32) -> f32 {
+    (uva * AS7331_ERYTHEMAL_WEIGHT_UVA + uvb * AS7331_ERYTHEMAL_WEIGHT_UVB) / 2.5
+}
+
+/// Fraction of a minimal erythemal dose (MED) received in `duration_s` at
+/// constant UVA/UVB irradiance in uW/cm^2.
+///
+/// Uses the erythemal weighting of [`uv_index_from_irradiance`] and one MED
+/// of 250 J/m^2, the typical value for skin type II.
+pub fn med_fraction(uva: f32, uvb: f32, duration_s: f32) -> f32 {
+    let erythemal_w_m2 = uv_index_from_irradiance(uva, uvb) * 2.5 * AS7331_UW_CM2_TO_W_M2;
+    erythemal_w_m2 * duration_s / AS7331_MED_J_M2
+}
+
+/// Photon flux in photons/(cm^2 s) for `irradiance` in uW/cm^2 of
+/// monochromatic light at `wavelength_nm`, using E = h * c / wavelength.
+pub fn photon_flux(irradiance: f32, wavelength_nm: f32) -> f32 {
+    irradiance * wavelength_nm * AS7331_PHOTONS_PER_UJ_NM
+}
+
+/// Setting changed by one of the `set_*` methods of [`As7331`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigField {
+    Gain,
+    IntegrationTime,
+    MeasurementMode,
+    Standby,
+    Cclk,
+    BreakTime,
+    SynEdges,
+    Divider,
+    /// OSR power down bit
+    PowerDown,
+    /// OSR start state bit
+    StartState,
+}
+
+/// Whether `field` can only be written in configuration mode.
+///
+/// All settings in CREG1 to EDGES are configuration registers and are only
+/// addressable there; OSR is reachable in both states.
+pub fn requires_config_mode(field: ConfigField) -> bool {
+    !matches!(field, ConfigField::PowerDown | ConfigField::StartState)
+}
+
+/// Packs gain (upper nibble) and integration time (lower nibble) into CREG1.
+pub fn pack_creg1(gain: u8, time: u8) -> u8 {
+    (gain & 0x0f) << 4 | (time & 0x0f)
+}
+
+/// Splits a CREG1 value into `(gain, time)`.
+pub fn unpack_creg1(creg1: u8) -> (u8, u8) {
+    (creg1 >> 4, creg1 & 0x0f)
+}
+
+/// Estimated average supply current in uA for `cfg` sampled at `sample_rate_hz`.
+///
+/// The device draws the active current while integrating. Between
+/// conversions it draws the standby current if SB is enabled, otherwise the
+/// active current. A rate of zero assumes the device is powered down.
+pub fn estimate_current_ua(cfg: &Config, sample_rate_hz: f32) -> f32 {
+    if sample_rate_hz <= 0.0 {
+        return AS7331_CURRENT_POWER_DOWN_UA;
+    }
+    let idle_ua = if cfg.sb == AS7331_CREG3_SB_ON {
+        AS7331_CURRENT_STANDBY_UA
+    } else {
+        AS7331_CURRENT_ACTIVE_UA
+    };
+    let duty = (integration_time_ms(cfg.time, cfg.cclk) * sample_rate_hz / 1000.0).min(1.0);
+    duty * AS7331_CURRENT_ACTIVE_UA + (1.0 - duty) * idle_ua
+}
+
+/// Whether `expected_irradiance` (uW/cm^2) on `channel` would exceed the
+/// full-scale count at `gain` and `time`, assuming CCLK = 1.024 MHz and no
+/// divider, see [`convert::full_scale_counts`].
+pub fn would_saturate(
+    expected_irradiance: f32,
+    gain: Gain,
+    time: IntegrationTime,
+    channel: Channel,
+) -> bool {
+    let lsb = convert::lsb(gain.bits(), time.bits(), AS7331_CREG3_CCLK_1024)[channel.index()];
+    let full_scale = convert::full_scale_counts(time.bits(), Divider::Off);
+    expected_irradiance / lsb > full_scale as f32
+}
+
+/// Addresses from 0x74 to 0x77 at which an AS7331 answers.
+///
+/// Reads AGEN at each address over any `embedded_hal` I2C bus and keeps the
+/// ones reporting the AS7331 device type. AGEN is only readable in
+/// configuration mode, the state after power-on or reset.
+#[cfg(feature = "heapless")]
+pub fn scan<I2C: embedded_hal::i2c::I2c>(bus: &mut I2C) -> heapless::Vec<u8, 4> {
+    let mut found = heapless::Vec::new();
+    for addr in AS7331_ADDRESSES {
+        let mut agen = [0u8; 1];
+        let ok = bus.write_read(addr, &[ConfigReg::Agen.addr()], &mut agen);
+        if ok.is_ok() && DeviceInfo::from_agen(agen[0]).device_type == AS7331_DEVICE_TYPE {
+            let _ = found.push(addr);
+        }
+    }
+    found
+}
+
+/// Triggers a one-shot on every device first, then reads them in order.
+///
+/// Keeps the skew between the sensors down to the trigger loop. All devices
+/// have to be in command mode. `timeout_ms` applies to each device, `delay`
+/// runs between the status polls.
+pub fn measure_array<R: RegisterAccess, D: DelayNs, const N: usize>(
+    devices: &mut [As7331<R>; N],
+    timeout_ms: u32,
+    delay: &mut D,
+) -> Result<[Measurement; N], As7331Error<R::Error>> {
+    for dev in devices.iter_mut() {
+        dev.one_shot()?;
+    }
+    let mut out = [Measurement::default(); N];
+    for (dev, m) in devices.iter_mut().zip(out.iter_mut()) {
+        dev.wait_for_data_with(timeout_ms, delay)?;
+        let raw = dev.read_all_data()?;
+        *m = dev.decode(raw);
+    }
+    Ok(out)
+}
+
+/// Endless iterator over continuous-mode results, see
+/// [`As7331::measurements_decimated`].
+pub struct Measurements<'d, R, D> {
+    dev: &'d mut As7331<R>,
+    delay: &'d mut D,
+    timeout_ms: u32,
+    keep_every: u32,
+    stall_detection: bool,
+}
+
+impl<R: RegisterAccess, D: DelayNs> Measurements<'_, R, D> {
+    /// Treats a result missing for `stall_timeout_ms` as a dead sensor.
+    ///
+    /// Replaces the timeout of the iterator: if NDATA does not come up in
+    /// time, the iterator yields [`As7331Error::Stalled`] instead of
+    /// [`As7331Error::Timeout`], so a logger can tell a wedged device from
+    /// a bus error and recover, e.g. with a reset.
+    pub fn stall_timeout_ms(mut self, stall_timeout_ms: u32) -> Self {
+        self.timeout_ms = stall_timeout_ms;
+        self.stall_detection = true;
+        self
+    }
+
+    fn next_result(&mut self) -> Result<Measurement, As7331Error<R::Error>> {
+        match self.dev.next_continuous(self.timeout_ms, self.delay) {
+            Err(As7331Error::Timeout) if self.stall_detection => Err(As7331Error::Stalled),
+            result => result,
+        }
+    }
+}
+
+impl<R: RegisterAccess, D: DelayNs> Iterator for Measurements<'_, R, D> {
+    type Item = Result<Measurement, As7331Error<R::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for _ in 1..self.keep_every {
+            if let Err(e) = self.next_result() {
+                return Some(Err(e));
+            }
+        }
+        Some(self.next_result())
+    }
+}
+
+/// [`As7331`] that keeps the last `N` results of
+/// [`measure_blocking`](Self::measure_blocking) (and `measure` with the
+/// `esp-idf` feature) and [`read`](Self::read), e.g. for a scrolling graph.
+///
+/// All other methods of the driver are reachable through `Deref`; only these
+/// record into the history.
+#[cfg(feature = "heapless")]
+pub struct HistoryRecorder<R, const N: usize> {
+    dev: As7331<R>,
+    history: heapless::HistoryBuffer<Measurement, N>,
+}
+
+#[cfg(feature = "heapless")]
+impl<R: RegisterAccess, const N: usize> HistoryRecorder<R, N> {
+    pub fn new(dev: As7331<R>) -> Self {
+        HistoryRecorder {
+            dev,
+            history: heapless::HistoryBuffer::new(),
+        }
+    }
+
+    /// [`As7331::measure`], recording the result.
+    #[cfg(feature = "esp-idf")]
+    pub fn measure(&mut self, timeout_ms: u32) -> Result<Measurement, As7331Error<R::Error>> {
+        self.measure_blocking(timeout_ms, &mut FreeRtos)
+    }
+
+    /// [`As7331::measure_blocking`], recording the result.
+    pub fn measure_blocking<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<Measurement, As7331Error<R::Error>> {
+        let m = self.dev.measure_blocking(timeout_ms, delay)?;
+        self.history.write(m);
+        Ok(m)
+    }
+
+    /// [`As7331::read`], recording the measurement of either result form.
+    pub fn read(&mut self) -> Result<ReadResult, As7331Error<R::Error>> {
+        let result = self.dev.read()?;
+        let m = match result {
+            ReadResult::Measurement(m) => m,
+            ReadResult::Synd { measurement, .. } => measurement,
+        };
+        self.history.write(m);
+        Ok(result)
+    }
+
+    /// Recorded results in buffer order.
+    ///
+    /// Once more than `N` results were recorded the buffer wraps and the
+    /// oldest entry is overwritten in place; use
+    /// [`history_ordered`](Self::history_ordered) for time order.
+    pub fn history(&self) -> &[Measurement] {
+        self.history.as_slice()
+    }
+
+    /// Recorded results from the oldest to the most recent.
+    pub fn history_ordered(&self) -> impl Iterator<Item = &Measurement> {
+        self.history.oldest_ordered()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Hands back the driver, dropping the history.
+    pub fn into_inner(self) -> As7331<R> {
+        self.dev
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<R, const N: usize> core::ops::Deref for HistoryRecorder<R, N> {
+    type Target = As7331<R>;
+
+    fn deref(&self) -> &As7331<R> {
+        &self.dev
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<R, const N: usize> core::ops::DerefMut for HistoryRecorder<R, N> {
+    fn deref_mut(&mut self) -> &mut As7331<R> {
+        &mut self.dev
+    }
+}
+
+/// Adapts an `embedded_io::Write` to `core::fmt::Write`, keeping the I/O error.
+#[cfg(feature = "embedded-io")]
+struct CsvWriter<'w, W: embedded_io::Write> {
+    inner: &'w mut W,
+    error: Result<(), W::Error>,
+}
+
+#[cfg(feature = "embedded-io")]
+impl<W: embedded_io::Write> core::fmt::Write for CsvWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Err(e);
+            core::fmt::Error
+        })
+    }
+}
+
+/// Register-level access to the device, the transport under [`As7331`].
+///
+/// Implemented for the ESP-IDF `I2cDriver` with the `esp-idf` feature; other
+/// buses, bridges or a simulator can be plugged in by implementing it. `addr`
+/// is the device address given to [`As7331::new`].
+pub trait RegisterAccess {
+    /// Error of a failed transfer, returned in [`As7331Error::I2c`].
+    type Error: core::fmt::Debug;
+
+    /// Reads `data.len()` bytes starting at `reg`.
+    fn read_regs(&mut self, addr: u8, reg: u8, data: &mut [u8]) -> Result<(), Self::Error>;
+
+    fn write_reg(&mut self, addr: u8, reg: u8, val: u8) -> Result<(), Self::Error>;
+
+    fn read_reg(&mut self, addr: u8, reg: u8) -> Result<u8, Self::Error> {
+        let mut data = [0u8; 1];
+        self.read_regs(addr, reg, &mut data)?;
+        Ok(data[0])
+    }
+
+    /// Whether a device ACKs at `addr`, see [`As7331::bus_healthy`].
+    ///
+    /// The default reads OSR; transports that can bound the transfer with a
+    /// timeout should do so, so that a bus held low also returns `false`.
+    fn probe(&mut self, addr: u8) -> bool {
+        self.read_reg(addr, ConfigReg::Osr.addr()).is_ok()
+    }
+}
+
+#[cfg(feature = "esp-idf")]
+impl RegisterAccess for I2cDriver<'_> {
+    type Error = EspError;
+
+    fn read_regs(&mut self, addr: u8, reg: u8, data: &mut [u8]) -> Result<(), EspError> {
+        self.write_read(addr, &[reg], data, BLOCK)
+    }
+
+    fn write_reg(&mut self, addr: u8, reg: u8, val: u8) -> Result<(), EspError> {
+        self.write(addr, &[reg, val], BLOCK)
+    }
+
+    /// Addresses OSR with a timeout instead of blocking.
+    fn probe(&mut self, addr: u8) -> bool {
+        let timeout = TickType::new_millis(AS7331_PROBE_TIMEOUT_MS).ticks();
+        self.write(addr, &[ConfigReg::Osr.addr()], timeout).is_ok()
+    }
+}
+
+pub struct As7331<R> {
+    pub i2c: R,
+    pub addr: u8,
+    config: Config,
+    conversions_read: u32,
+    conversions_missed: u32,
+    window_transmission: [f32; 3],
+    tracing: bool,
+    wavelengths_nm: [f32; 3],
+    calibration: Calibration,
+    dos: u8,
+    read_selection: ReadSelection,
+    retry_count: u8,
+    noise: NoiseModel,
+    warmup_samples: u8,
+    saturation: SaturationTracker,
+    correction: [[f32; 3]; 3],
+}
+
+#[allow(dead_code)]
+impl<R: RegisterAccess> As7331<R> {
+    pub fn new(i2c: R, addr: u8) -> Self {
+        As7331 {
+            i2c,
+            addr,
+            config: Config::default(),
+            conversions_read: 0,
+            conversions_missed: 0,
+            window_transmission: [1.0; 3],
+            tracing: true,
+            wavelengths_nm: [
+                AS7331_WAVELENGTH_UVA_NM,
+                AS7331_WAVELENGTH_UVB_NM,
+                AS7331_WAVELENGTH_UVC_NM,
+            ],
+            calibration: Calibration::default(),
+            dos: AS7331_OSR_DOS_CONFIGURATION,
+            read_selection: ReadSelection::ALL,
+            retry_count: 0,
+            noise: NoiseModel::default(),
+            warmup_samples: AS7331_WARMUP_SAMPLES,
+            saturation: SaturationTracker::default(),
+            correction: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Whether the device ACKs a short probe at its address, see
+    /// [`RegisterAccess::probe`].
+    ///
+    /// On the ESP-IDF `I2cDriver` the probe uses a timeout instead of
+    /// blocking, so it also returns (`false`) on a bus held low. If a slave
+    /// holds SDA low after an interrupted transfer, the usual recovery is to
+    /// drive SCL as a GPIO for nine clock pulses with SDA released and then
+    /// generate a STOP condition (SDA low to high while SCL is high). This has
+    /// to run on the pins before the bus driver is created, e.g. before
+    /// [`try_new`](Self::try_new).
+    pub fn bus_healthy(&mut self) -> bool {
+        self.i2c.probe(self.addr)
+    }
+
+    /// Like [`new`](Self::new), but checks that an AS7331 answers at `addr`.
+    ///
+    /// On failure the bus is handed back together with the error.
+    pub fn try_new(i2c: R, addr: u8) -> Result<Self, (R, As7331Error<R::Error>)> {
+        let mut dev = Self::new(i2c, addr);
+        match dev.get_chip_id() {
+            Ok(id) if DeviceInfo::from_agen(id).device_type == AS7331_DEVICE_TYPE => Ok(dev),
+            Ok(id) => Err((dev.destroy(), As7331Error::WrongChipId(id))),
+            Err(e) => Err((dev.destroy(), e.into())),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Sets the transmission (0.0 to 1.0] of an optical window in front of the
+    /// sensor per channel.
+    ///
+    /// Irradiance values are divided by these factors so they describe the
+    /// light incident on the window rather than on the sensor. The default is
+    /// 1.0 (no window). [`read_scaled_fixed`](Self::read_scaled_fixed) avoids
+    /// floating point and reports the irradiance at the sensor.
+    ///
+    /// A factor outside (0.0, 1.0] fails with [`As7331Error::InvalidConfig`],
+    /// carrying its `f32` bits as the value, and keeps the previous factors.
+    pub fn set_window_transmission(
+        &mut self,
+        uva: f32,
+        uvb: f32,
+        uvc: f32,
+    ) -> Result<(), As7331Error<R::Error>> {
+        let factors = [uva, uvb, uvc];
+        if let Some(t) = factors.iter().find(|t| !(**t > 0.0 && **t <= 1.0)) {
+            return Err(As7331Error::InvalidConfig {
+                field: "window_transmission",
+                value: t.to_bits(),
+            });
+        }
+        self.window_transmission = factors;
+        Ok(())
+    }
+
+    /// Sets the calibration applied to all irradiance values.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    /// Sets the die temperature in Celsius at which the calibration applies
+    /// (default 25). Lets the temperature compensation match the conditions
+    /// of the calibration lab.
+    pub fn set_reference_temperature(&mut self, celsius: f32) {
+        self.calibration.reference_temperature = celsius;
+    }
+
+    /// Sets the wavelengths in nm used by
+    /// [`read_photon_flux`](Self::read_photon_flux), by default the peak
+    /// responsivity of each channel.
+    pub fn set_effective_wavelengths(&mut self, uva: f32, uvb: f32, uvc: f32) {
+        self.wavelengths_nm = [uva, uvb, uvc];
+    }
+
+    /// Selects the result registers read by [`measure_blocking`](Self::measure_blocking) and
+    /// [`read`](Self::read), see [`ReadSelection`].
+    pub fn set_read_selection(&mut self, selection: ReadSelection) {
+        self.read_selection = selection;
+    }
+
+    pub fn read_selection(&self) -> ReadSelection {
+        self.read_selection
+    }
+
+    /// Sets the noise used by
+    /// [`measurement_uncertainty`](Self::measurement_uncertainty).
+    pub fn set_noise_model(&mut self, noise: NoiseModel) {
+        self.noise = noise;
+    }
+
+    /// Sets the number of measurements discarded by
+    /// [`reset_to_defaults`](Self::reset_to_defaults) and
+    /// [`wake`](Self::wake) before they return (default 1).
+    pub fn set_warmup_samples(&mut self, samples: u8) {
+        self.warmup_samples = samples;
+    }
+
+    /// Sets the cross-talk correction applied by
+    /// [`read_uv_corrected`](Self::read_uv_corrected).
+    ///
+    /// Row `i` gives the weights of the measured UVA, UVB, UVC irradiance in
+    /// corrected channel `i`. The default is the identity.
+    pub fn set_correction_matrix(&mut self, m: [[f32; 3]; 3]) {
+        self.correction = m;
+    }
+
+    /// Sets the number of recent reads of
+    /// [`read_channel_validated`](Self::read_channel_validated) that
+    /// [`saturation_rate`](Self::saturation_rate) covers (default 32, at most
+    /// 64), clearing the recorded reads.
+    pub fn set_saturation_window(&mut self, window: u8) {
+        self.saturation = SaturationTracker::new(window);
+    }
+
+    /// Fraction of the recent [`read_channel_validated`](Self::read_channel_validated)
+    /// calls that found the channel saturated. A high rate suggests a lower
+    /// gain.
+    pub fn saturation_rate(&self) -> f32 {
+        self.saturation.saturation_rate()
+    }
+
+    /// Sets how often a failed bus transaction is repeated before its error
+    /// is returned (default 0, no retries).
+    ///
+    /// Helps against single NAKs on noisy buses. Every register access of
+    /// the driver is retried, including the OSR writes.
+    pub fn set_retry_count(&mut self, retries: u8) {
+        self.retry_count = retries;
+    }
+
+    /// Enables or disables the `debug!` trace of every I2C transaction
+    /// (enabled by default), independent of the global log level.
+    pub fn set_tracing(&mut self, on: bool) {
+        self.tracing = on;
+    }
+
+    pub fn destroy(self) -> R {
+        self.i2c
+    }
+
+    pub fn get_chip_id(&mut self) -> Result<u8, R::Error> {
+        let mut data = [0u8; 1];
+        self.i2c_write_read_cmd(ConfigReg::Agen, &mut data)?;
+        Ok(data[0])
+    }
+
+    pub fn device_type(&mut self) -> Result<u8, R::Error> {
+        Ok(self.device_info()?.device_type)
+    }
+
+    pub fn mutation(&mut self) -> Result<u8, R::Error> {
+        Ok(self.device_info()?.mutation)
+    }
+
+    pub fn device_info(&mut self) -> Result<DeviceInfo, R::Error> {
+        Ok(DeviceInfo::from_agen(self.get_chip_id()?))
+    }
+
+    /// Whether the device is still in its power-on state: powered down in
+    /// configuration mode with all configuration registers at reset values.
+    ///
+    /// `false` means it was configured since power-up, e.g. by an earlier run
+    /// of the application. The power-on state is configuration mode, so a
+    /// device in measurement mode is reported as configured without reading the
+    /// configuration registers.
+    pub fn is_in_power_on_default(&mut self) -> Result<bool, R::Error> {
+        let mut osr = [0u8; 1];
+        self.i2c_write_read_cmd(ConfigReg::Osr, &mut osr)?;
+        if osr[0] != AS7331_OSR_DEFAULT {
+            return Ok(false);
+        }
+        let mut regs = [0u8; 6];
+        self.i2c_read_bytes(ConfigReg::Creg1, &mut regs)?;
+        Ok(regs == AS7331_CONFIG_DEFAULTS)
+    }
+
+    pub fn init(
+        &mut self,
+        mode: MeasurementMode,
+        cclk: u8,
+        sb: u8,
+        break_time: u8,
+        gain: u8,
+        time: u8,
+    ) -> Result<(), R::Error> {
+        let mmode = mode.bits();
+        self.i2c_write_cmd(ConfigReg::Creg1, pack_creg1(gain, time))?;
+        self.i2c_write_cmd(ConfigReg::Creg3, mmode << 6 | sb << 4 | cclk)?;
+        self.i2c_write_cmd(ConfigReg::Break, break_time)?;
+        self.config = Config {
+            mmode,
+            cclk,
+            sb,
+            break_time,
+            gain,
+            time,
+            divider: self.config.divider,
+            edges: self.config.edges,
+        };
+        Ok(())
+    }
+
+    /// Writes all registers of `cfg`: [`init`](Self::init) plus the divider
+    /// and EDGES.
+    ///
+    /// `cfg` is checked with [`Config::validate`] before anything is written.
+    pub fn apply(&mut self, cfg: &Config) -> Result<(), As7331Error<R::Error>> {
+        cfg.validate()?;
+        self.init(
+            MeasurementMode::from_bits(cfg.mmode),
+            cfg.cclk,
+            cfg.sb,
+            cfg.break_time,
+            cfg.gain,
+            cfg.time,
+        )?;
+        self.write_divider(cfg.divider)?;
+        self.i2c_write_cmd(ConfigReg::Edges, cfg.edges)?;
+        self.config.edges = cfg.edges;
+        Ok(())
+    }
+
+    /// Like [`apply`](Self::apply), but reads every register back via
+    /// [`write_register_verified`](Self::write_register_verified).
+    pub fn apply_verified(&mut self, cfg: &Config) -> Result<(), As7331Error<R::Error>> {
+        cfg.validate()?;
+        self.write_register_verified(ConfigReg::Creg1, pack_creg1(cfg.gain, cfg.time))?;
+        let mut creg2 = [0u8; 1];
+        self.i2c_write_read_cmd(ConfigReg::Creg2, &mut creg2)?;
+        self.write_register_verified(
+            ConfigReg::Creg2,
+            (creg2[0] & !AS7331_CREG2_DIV_MASK) | cfg.divider.bits(),
+        )?;
+        self.write_register_verified(ConfigReg::Creg3, cfg.mmode << 6 | cfg.sb << 4 | cfg.cclk)?;
+        self.write_register_verified(ConfigReg::Break, cfg.break_time)?;
+        self.write_register_verified(ConfigReg::Edges, cfg.edges)?;
+        self.config = *cfg;
+        Ok(())
+    }
+
+    /// Checks after [`apply`](Self::apply) that the registers hold `cfg`.
+    ///
+    /// Configuration writes outside configuration mode are silently lost, so
+    /// on a mismatch this enters configuration mode, applies `cfg` once more
+    /// and checks again. A remaining mismatch is reported as
+    /// [`As7331Error::WriteVerifyFailed`] for the first differing register.
+    /// The device is left in configuration mode.
+    pub fn verify_config_applied(&mut self, cfg: &Config) -> Result<(), As7331Error<R::Error>> {
+        if self.config_mismatch(cfg)?.is_none() {
+            return Ok(());
+        }
+        if self.tracing {
+            debug!("AS7331 config not applied, retrying in configuration mode");
+        }
+        self.set_configuration_mode()?;
+        self.apply(cfg)?;
+        match self.config_mismatch(cfg)? {
+            None => Ok(()),
+            Some(e) => Err(e),
+        }
+    }
+
+    /// Reads CREG1 to EDGES and compares them with the values of `cfg`.
+    fn config_mismatch(&mut self, cfg: &Config) -> Result<Option<As7331Error<R::Error>>, R::Error> {
+        let mut regs = [0u8; 5];
+        self.i2c_read_bytes(ConfigReg::Creg1, &mut regs)?;
+        let expected = [
+            pack_creg1(cfg.gain, cfg.time),
+            (regs[1] & !AS7331_CREG2_DIV_MASK) | cfg.divider.bits(),
+            cfg.mmode << 6 | cfg.sb << 4 | cfg.cclk,
+            cfg.break_time,
+            cfg.edges,
+        ];
+        let first = (0..regs.len()).find(|&i| regs[i] != expected[i]);
+        Ok(first.map(|i| As7331Error::WriteVerifyFailed {
+            reg: ConfigReg::Creg1.addr() + i as u8,
+            wrote: expected[i],
+            read: regs[i],
+        }))
+    }
+
+    /// Writes a configuration register and checks it by reading it back.
+    ///
+    /// Catches writes corrupted on the bus or ignored by the device. Only
+    /// valid in configuration mode, where the registers can be read.
+    pub fn write_register_verified(
+        &mut self,
+        reg: ConfigReg,
+        val: u8,
+    ) -> Result<(), As7331Error<R::Error>> {
+        self.i2c_write_cmd(reg, val)?;
+        let mut data = [0u8; 1];
+        self.i2c_write_read_cmd(reg, &mut data)?;
+        if data[0] != val {
+            return Err(As7331Error::WriteVerifyFailed {
+                reg: reg.addr(),
+                wrote: val,
+                read: data[0],
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads CREG1, CREG2, CREG3, BREAK and EDGES back from the device.
+    ///
+    /// Only valid in configuration mode.
+    pub fn read_config(&mut self) -> Result<Config, R::Error> {
+        let mut regs = [0u8; 5];
+        self.i2c_read_bytes(ConfigReg::Creg1, &mut regs)?;
+        Ok(Config::from_registers(regs))
+    }
+
+    /// Whether the configuration registers still hold `expected`.
+    ///
+    /// A mismatch means the device was reset or its registers were changed
+    /// behind the driver, and the configuration should be applied again.
+    /// Fails with [`As7331Error::WrongMode`] outside configuration mode.
+    pub fn config_matches(&mut self, expected: &Config) -> Result<bool, As7331Error<R::Error>> {
+        if self.dos != AS7331_OSR_DOS_CONFIGURATION {
+            return Err(As7331Error::WrongMode);
+        }
+        Ok(self.read_config()? == *expected)
+    }
+
+    /// Reads the divider setting from CREG2. Only valid in configuration mode.
+    pub fn get_divider(&mut self) -> Result<Divider, R::Error> {
+        let mut data = [0u8; 1];
+        self.i2c_write_read_cmd(ConfigReg::Creg2, &mut data)?;
+        Ok(Divider::from_creg2(data[0]))
+    }
+
+    /// Sets the result divider, passing through configuration mode.
+    ///
+    /// The prior OSR is restored afterwards, so CONT conversions resume.
+    pub fn set_divider(&mut self, divider: Divider) -> Result<(), R::Error> {
+        self.write_field(ConfigField::Divider, |dev| dev.write_divider(divider))
+    }
+
+    /// Runs `write`, entering configuration mode first if `field` needs it
+    /// (see [`requires_config_mode`]) and restoring the OSR read before the
+    /// switch afterwards. Restoring SS along with DOS keeps a CONT
+    /// measurement running; in CMD mode SS is already clear once the last
+    /// conversion finished.
+    fn write_field<F>(&mut self, field: ConfigField, write: F) -> Result<(), R::Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), R::Error>,
+    {
+        let prev = if requires_config_mode(field) && self.dos != AS7331_OSR_DOS_CONFIGURATION {
+            Some(self.read_osr()?)
+        } else {
+            None
+        };
+        if prev.is_some() {
+            self.set_configuration_mode()?;
+        }
+        write(self)?;
+        if let Some(osr) = prev {
+            let osr = Osr {
+                sw_res: false,
+                ..osr
+            };
+            self.i2c_write_cmd(ConfigReg::Osr, osr.to_byte())?;
+        }
+        Ok(())
+    }
+
+    fn write_divider(&mut self, divider: Divider) -> Result<(), R::Error> {
+        let mut data = [0u8; 1];
+        self.i2c_write_read_cmd(ConfigReg::Creg2, &mut data)?;
+        self.i2c_write_cmd(
+            ConfigReg::Creg2,
+            (data[0] & !AS7331_CREG2_DIV_MASK) | divider.bits(),
+        )?;
+        self.config.divider = divider;
+        Ok(())
+    }
+
+    /// Writes only the registers of `cfg` that differ from the stored config
+    /// and returns whether anything was written.
+    ///
+    /// Like [`apply`](Self::apply) this expects configuration mode. The stored
+    /// config starts at the reset defaults, so it only reflects the device if
+    /// all changes went through this driver.
+    pub fn apply_if_changed(&mut self, cfg: &Config) -> Result<bool, As7331Error<R::Error>> {
+        cfg.validate()?;
+        let old = self.config;
+        let mut written = false;
+        if (cfg.gain, cfg.time) != (old.gain, old.time) {
+            self.i2c_write_cmd(ConfigReg::Creg1, pack_creg1(cfg.gain, cfg.time))?;
+            self.config.gain = cfg.gain;
+            self.config.time = cfg.time;
+            written = true;
+        }
+        if (cfg.mmode, cfg.sb, cfg.cclk) != (old.mmode, old.sb, old.cclk) {
+            self.i2c_write_cmd(ConfigReg::Creg3, cfg.mmode << 6 | cfg.sb << 4 | cfg.cclk)?;
+            self.config.mmode = cfg.mmode;
+            self.config.sb = cfg.sb;
+            self.config.cclk = cfg.cclk;
+            written = true;
+        }
+        if cfg.break_time != old.break_time {
+            self.i2c_write_cmd(ConfigReg::Break, cfg.break_time)?;
+            self.config.break_time = cfg.break_time;
+            written = true;
+        }
+        if cfg.divider != old.divider {
+            self.write_divider(cfg.divider)?;
+            written = true;
+        }
+        if cfg.edges != old.edges {
+            self.i2c_write_cmd(ConfigReg::Edges, cfg.edges)?;
+            self.config.edges = cfg.edges;
+            written = true;
+        }
+        Ok(written)
+    }
+
+    /// Changes only the gain, passing through configuration mode.
+    ///
+    /// The prior OSR is restored afterwards, so CONT conversions resume.
+    pub fn set_gain(&mut self, gain: u8) -> Result<(), R::Error> {
+        self.write_field(ConfigField::Gain, |dev| {
+            dev.i2c_write_cmd(ConfigReg::Creg1, pack_creg1(gain, dev.config.time))?;
+            dev.config.gain = gain;
+            Ok(())
+        })
+    }
+
+    /// Switches the measurement mode, read-modify-writing only the MMODE bits
+    /// of CREG3.
+    ///
+    /// Unlike the other setters this does not pass through configuration
+    /// mode: switching the mode under a running measurement is an error of
+    /// the caller, so it fails with [`As7331Error::WrongMode`] unless the
+    /// device is in configuration mode as last written through this driver.
+    pub fn set_measurement_mode_mmode(
+        &mut self,
+        mode: MeasurementMode,
+    ) -> Result<(), As7331Error<R::Error>> {
+        if self.dos != AS7331_OSR_DOS_CONFIGURATION {
+            return Err(As7331Error::WrongMode);
+        }
+        let mut data = [0u8; 1];
+        self.i2c_write_read_cmd(ConfigReg::Creg3, &mut data)?;
+        let creg3 = (data[0] & !AS7331_CREG3_MMODE_MASK) | mode.bits() << 6;
+        self.i2c_write_cmd(ConfigReg::Creg3, creg3)?;
+        self.config.mmode = mode.bits();
+        Ok(())
+    }
+
+    /// Changes only the conversion clock, passing through configuration mode.
+    ///
+    /// Read-modify-writes the CCLK bits of CREG3 and restores the prior
+    /// OSR, so CONT conversions resume. The stored config follows, so
+    /// the integration time and irradiance scaling stay correct.
+    pub fn set_cclk(&mut self, cclk: Cclk) -> Result<(), R::Error> {
+        self.write_field(ConfigField::Cclk, |dev| {
+            let mut data = [0u8; 1];
+            dev.i2c_write_read_cmd(ConfigReg::Creg3, &mut data)?;
+            let creg3 = (data[0] & !AS7331_CREG3_CCLK_MASK) | cclk.bits();
+            dev.i2c_write_cmd(ConfigReg::Creg3, creg3)?;
+            dev.config.cclk = cclk.bits();
+            Ok(())
+        })
+    }
+
+    /// Puts the device into standby and returns whether STANDBYSTATE confirms it.
+    ///
+    /// Standby is enabled by the SB bit of CREG3, which can only be written in
+    /// configuration mode, and takes effect once the device is back in
+    /// measurement mode. With SB set in [`init`](Self::init) the device already
+    /// idles in standby between measurements; this pair switches it explicitly
+    /// at runtime and keeps the stored config in sync.
+    ///
+    /// The prior OSR is restored afterwards. STATUS only exists in measurement
+    /// mode, so from configuration mode the bit is written but the result is
+    /// `false`.
+    pub fn enter_standby(&mut self) -> Result<bool, R::Error> {
+        self.write_standby(AS7331_CREG3_SB_ON)?;
+        Ok(self.read_standby_state()? == Some(true))
+    }
+
+    /// Leaves standby and returns whether STANDBYSTATE confirms it, see
+    /// [`enter_standby`](Self::enter_standby).
+    pub fn exit_standby(&mut self) -> Result<bool, R::Error> {
+        self.write_standby(AS7331_CREG3_SB_OFF)?;
+        Ok(self.read_standby_state()? == Some(false))
+    }
+
+    fn write_standby(&mut self, sb: u8) -> Result<(), R::Error> {
+        self.write_field(ConfigField::Standby, |dev| {
+            let mut data = [0u8; 1];
+            dev.i2c_write_read_cmd(ConfigReg::Creg3, &mut data)?;
+            dev.i2c_write_cmd(
+                ConfigReg::Creg3,
+                (data[0] & !AS7331_CREG3_SB_MASK) | sb << 4,
+            )?;
+            dev.config.sb = sb;
+            Ok(())
+        })
+    }
+
+    /// STANDBYSTATE, or `None` outside measurement mode.
+    fn read_standby_state(&mut self) -> Result<Option<bool>, R::Error> {
+        if self.dos != AS7331_OSR_DOS_MEASUREMENT {
+            return Ok(None);
+        }
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        Ok(Some(data[1] & AS7331_STATUS_STANDBYSTATE != 0))
+    }
+
+    /// Sets the pause between two measurements in steps of 8 us.
+    ///
+    /// BREAK is unused in CMD mode, so any value is accepted there. In CONT and
+    /// SYND mode a zero break leaves no time to read a result before the next
+    /// one overwrites it and is rejected.
+    pub fn set_break_time(&mut self, break_time: u8) -> Result<(), As7331Error<R::Error>> {
+        let back_to_back = matches!(
+            self.config.mmode,
+            AS7331_CREG3_MMODE_CONT | AS7331_CREG3_MMODE_SYND
+        );
+        if back_to_back && break_time == 0 {
+            return Err(As7331Error::InvalidConfig {
+                field: "break_time",
+                value: break_time as u32,
+            });
+        }
+        self.write_field(ConfigField::BreakTime, |dev| {
+            dev.i2c_write_cmd(ConfigReg::Break, break_time)?;
+            dev.config.break_time = break_time;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Reads BREAK and CCLK back from the device and returns the pause
+    /// between measurements in us.
+    ///
+    /// Only valid in configuration mode. [`read_config`](Self::read_config)
+    /// gives the same value through [`Config::break_time_us`].
+    pub fn get_break_time_us(&mut self) -> Result<u32, R::Error> {
+        let mut regs = [0u8; 2];
+        self.i2c_read_bytes(ConfigReg::Creg3, &mut regs)?;
+        Ok(convert::break_time_us(
+            regs[1],
+            regs[0] & AS7331_CREG3_CCLK_MASK,
+        ))
+    }
+
+    /// Sets the number of SYN edges that end a measurement in SYND mode.
+    ///
+    /// The measurement starts with a SYN edge and ends with the `edges`-th
+    /// following one, so the exposure is `edges` periods of the SYN signal.
+    /// The device counts the conversion clock in between (OUTCONV), which
+    /// gives the exact window. `edges` must be at least 1. The edge polarity
+    /// is fixed, see [`set_syn_edge_polarity`](Self::set_syn_edge_polarity).
+    pub fn set_syn_edges(&mut self, edges: u8) -> Result<(), As7331Error<R::Error>> {
+        if edges == 0 {
+            return Err(As7331Error::InvalidConfig {
+                field: "edges",
+                value: edges as u32,
+            });
+        }
+        self.write_field(ConfigField::SynEdges, |dev| {
+            dev.i2c_write_cmd(ConfigReg::Edges, edges)?;
+            dev.config.edges = edges;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Selects the SYN edge that starts and ends a synchronized measurement.
+    ///
+    /// The device reacts to falling SYN edges only. Neither EDGES nor OPTREG
+    /// has a polarity bit, so `rising == false` is accepted without a bus
+    /// transfer and `rising == true` fails with
+    /// [`As7331Error::InvalidConfig`]; a trigger signal with active rising
+    /// edges has to be inverted in hardware.
+    pub fn set_syn_edge_polarity(&mut self, rising: bool) -> Result<(), As7331Error<R::Error>> {
+        if rising {
+            return Err(As7331Error::InvalidConfig {
+                field: "syn_edge_polarity",
+                value: rising as u32,
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the EDGES register. Only valid in configuration mode.
+    pub fn get_syn_edges(&mut self) -> Result<u8, R::Error> {
+        let mut data = [0u8; 1];
+        self.i2c_write_read_cmd(ConfigReg::Edges, &mut data)?;
+        Ok(data[0])
+    }
+
+    pub fn one_shot(&mut self) -> Result<(), R::Error> {
+        let mut data = [0u8; 1];
+        self.i2c_write_read_cmd(ConfigReg::Osr, &mut data)?;
+        self.i2c_write_cmd(ConfigReg::Osr, data[0] | 0x80)
+    }
+
+    pub fn get_status(&mut self) -> Result<[u8; 8], R::Error> {
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        Ok([
+            data[1] & 0x01,
+            (data[1] & 0x02) >> 1,
+            (data[1] & 0x04) >> 2,
+            (data[1] & 0x08) >> 3,
+            (data[1] & 0x10) >> 4,
+            (data[1] & 0x20) >> 5,
+            (data[1] & 0x40) >> 6,
+            (data[1] & 0x80) >> 7,
+        ])
+    }
+
+    /// Status word without bit-splitting: STATUS in the high byte, OSR in the low byte.
+    pub fn get_status_raw(&mut self) -> Result<u16, R::Error> {
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+    }
+
+    pub fn adc_overflowed(&mut self) -> Result<bool, R::Error> {
+        Ok(self.read_status_byte()? & AS7331_STATUS_ADCOF != 0)
+    }
+
+    pub fn mres_overflowed(&mut self) -> Result<bool, R::Error> {
+        Ok(self.read_status_byte()? & AS7331_STATUS_MRESOF != 0)
+    }
+
+    pub fn conversion_overflowed(&mut self) -> Result<bool, R::Error> {
+        Ok(self.read_status_byte()? & AS7331_STATUS_OUTCONVOF != 0)
+    }
+
+    /// First set overflow flag in the order ADCOF, MRESOF, OUTCONVOF.
+    pub fn overflow_reason(&mut self) -> Result<Option<OverflowReason>, R::Error> {
+        let status = self.read_status_byte()?;
+        Ok(if status & AS7331_STATUS_ADCOF != 0 {
+            Some(OverflowReason::Adc)
+        } else if status & AS7331_STATUS_MRESOF != 0 {
+            Some(OverflowReason::Mres)
+        } else if status & AS7331_STATUS_OUTCONVOF != 0 {
+            Some(OverflowReason::Conversion)
+        } else {
+            None
+        })
+    }
+
+    pub fn read_status(&mut self) -> Result<Status, R::Error> {
+        Ok(Status::from_byte(self.read_status_byte()?))
+    }
+
+    /// Reads the status and clears its latched flags.
+    ///
+    /// NDATA, LDATA and the overflow flags stay set until the results they
+    /// refer to are read, so this reads STATUS and the complete result block
+    /// (TEMP to MRES3) in one transfer and discards the results. Returns the
+    /// status as it was before clearing.
+    pub fn read_and_clear_status(&mut self) -> Result<Status, R::Error> {
+        let mut data = [0u8; 10];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        Ok(Status::from_byte(data[1]))
+    }
+
+    fn read_status_byte(&mut self) -> Result<u8, R::Error> {
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        Ok(data[1])
+    }
+
+    pub fn read_temp_data(&mut self) -> Result<u16, R::Error> {
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Temp, &mut data)?;
+        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+    }
+
+    /// Reads TEMP up to `n` times back to back into `out` and returns the
+    /// number of samples captured, at most `out.len()`.
+    ///
+    /// The raw values are stored as read, without waiting for new results, so
+    /// the bus speed sets the sample rate. Meant for noise characterization.
+    pub fn read_temperature_burst(&mut self, n: u16, out: &mut [u16]) -> Result<usize, R::Error> {
+        let count = out.len().min(n as usize);
+        for sample in out[..count].iter_mut() {
+            *sample = self.read_temp_data()?;
+        }
+        Ok(count)
+    }
+
+    /// Reads TEMP and returns the die temperature in Fahrenheit, see
+    /// [`convert::celsius_to_fahrenheit`].
+    pub fn read_temperature_fahrenheit(&mut self) -> Result<f32, R::Error> {
+        let raw = self.read_temp_data()?;
+        Ok(convert::celsius_to_fahrenheit(temperature_celsius(raw)))
+    }
+
+    /// Reads TEMP and returns the die temperature in Kelvin, see
+    /// [`convert::celsius_to_kelvin`].
+    pub fn read_temperature_kelvin(&mut self) -> Result<f32, R::Error> {
+        let raw = self.read_temp_data()?;
+        Ok(convert::celsius_to_kelvin(temperature_celsius(raw)))
+    }
+
+    /// Temperature in Celsius, or [`As7331Error::TemperatureOutOfRange`] if
+    /// the raw value is outside [`convert::TEMP_RAW_VALID`].
+    ///
+    /// Outside the operating range of -40 to 85 Celsius the linear formula
+    /// is inaccurate, and values far outside it point to a fault rather than
+    /// a real temperature.
+    pub fn read_temperature_checked(&mut self) -> Result<f32, As7331Error<R::Error>> {
+        let raw = self.read_temp_data()?;
+        if !convert::temperature_in_range(raw) {
+            return Err(As7331Error::TemperatureOutOfRange(raw));
+        }
+        Ok(temperature_celsius(raw))
+    }
+
+    /// Temperature in Celsius if a new result is available, `None` otherwise.
+    ///
+    /// Reads STATUS and TEMP in one transfer without waiting. The UV results
+    /// are not touched, so a logger reading them in continuous mode still
+    /// sees NDATA and gets every conversion.
+    pub fn read_temperature_nonblocking(&mut self) -> Result<Option<f32>, As7331Error<R::Error>> {
+        let mut data = [0u8; 4];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        if data[1] & AS7331_STATUS_NDATA == 0 {
+            return Ok(None);
+        }
+        let raw = ((data[3] as u16) << 8) | (data[2] as u16);
+        Ok(Some(temperature_celsius(raw)))
+    }
+
+    pub fn read_uv_a_data(&mut self) -> Result<u16, R::Error> {
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Mres1, &mut data)?;
+        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+    }
+
+    pub fn read_uv_b_data(&mut self) -> Result<u16, R::Error> {
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Mres2, &mut data)?;
+        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+    }
+
+    pub fn read_uv_c_data(&mut self) -> Result<u16, R::Error> {
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Mres3, &mut data)?;
+        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+    }
+
+    /// Reads one channel and fails with [`As7331Error::Saturated`] if that
+    /// channel overflowed.
+    ///
+    /// The overflow flags in STATUS are shared by all channels, so a set
+    /// ADCOF or MRESOF is only attributed to the channel if its result is
+    /// clipped at the full-scale count, see [`convert::full_scale_counts`].
+    /// STATUS and the result are read in one transfer.
+    pub fn read_channel_validated(
+        &mut self,
+        channel: Channel,
+    ) -> Result<u16, As7331Error<R::Error>> {
+        let offset = 4 + 2 * channel.index();
+        let mut data = [0u8; 10];
+        self.i2c_read_bytes(MeasReg::Status, &mut data[..offset + 2])?;
+        let counts = ((data[offset + 1] as u16) << 8) | (data[offset] as u16);
+        let overflow = data[1] & (AS7331_STATUS_ADCOF | AS7331_STATUS_MRESOF) != 0;
+        let saturated = overflow && counts >= self.full_scale_counts();
+        self.saturation.record(saturated);
+        if saturated {
+            return Err(As7331Error::Saturated);
+        }
+        Ok(counts)
+    }
+
+    /// Reads one channel as the signed difference from `baseline` counts,
+    /// e.g. a dark or ambient reading taken before.
+    ///
+    /// Both values are 16-bit, so the difference always fits into `i32`.
+    pub fn read_channel_differential(
+        &mut self,
+        channel: Channel,
+        baseline: u16,
+    ) -> Result<i32, R::Error> {
+        let counts = match channel {
+            Channel::Uva => self.read_uv_a_data()?,
+            Channel::Uvb => self.read_uv_b_data()?,
+            Channel::Uvc => self.read_uv_c_data()?,
+        };
+        Ok(counts as i32 - baseline as i32)
+    }
+
+    /// Reads one channel as the count before the divider, i.e. the raw count
+    /// times the configured divider factor.
+    ///
+    /// With the divider active the result covers up to `0xFFFF * factor`
+    /// counts, beyond the 16-bit register; without it this is the raw count.
+    pub fn read_channel_extended(&mut self, channel: Channel) -> Result<u32, R::Error> {
+        let counts = match channel {
+            Channel::Uva => self.read_uv_a_data()?,
+            Channel::Uvb => self.read_uv_b_data()?,
+            Channel::Uvc => self.read_uv_c_data()?,
+        };
+        Ok(counts as u32 * self.config.divider.factor())
+    }
+
+    /// [`read_channel_extended`](Self::read_channel_extended) as a float
+    /// count with the bits cut off by the divider estimated.
+    ///
+    /// The AS7331 has no result register wider than 16 bits: with the
+    /// divider active the ADC count is shifted right by DIV+1 before it is
+    /// stored. The dropped low bits are unknown, so their mean,
+    /// `(factor - 1) / 2`, is added, which halves the worst-case error of
+    /// the reconstruction. Without the divider this is the raw count.
+    ///
+    /// A register at 0 or at the full-scale count, see
+    /// [`convert::full_scale_counts`], is returned without the estimate: a
+    /// dark reading stays 0 and a clipped one does not exceed full scale.
+    pub fn read_channel_full_precision(
+        &mut self,
+        channel: Channel,
+    ) -> Result<f32, As7331Error<R::Error>> {
+        let counts = self.read_channel_extended(channel)?;
+        let factor = self.config.divider.factor();
+        let raw = counts / factor;
+        if raw == 0 || raw >= self.full_scale_counts() as u32 {
+            return Ok(counts as f32);
+        }
+        Ok(counts as f32 + (factor - 1) as f32 / 2.0)
+    }
+
+    /// Reads only MRES3, for applications that need UVC alone.
+    ///
+    /// This is a single two-byte transfer instead of the eight-byte block of
+    /// [`read_all_data`](Self::read_all_data). The AS7331 cannot disable
+    /// individual channels, so the conversion itself takes as long as before;
+    /// only bus time is saved.
+    pub fn read_uvc_fast(&mut self) -> Result<u16, R::Error> {
+        self.read_uv_c_data()
+    }
+
+    /// Reads the registers of the read selection, one transfer for each run
+    /// of adjacent registers. Unselected entries are 0.
+    fn read_selected(&mut self) -> Result<[u16; 4], R::Error> {
+        let sel = self.read_selection.bits();
+        if sel == ReadSelection::ALL.bits() {
+            return self.read_all_data();
+        }
+        let mut raw = [0u16; 4];
+        let mut i = 0;
+        while i < raw.len() {
+            if sel & (1 << i) == 0 {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < raw.len() && sel & (1 << i) != 0 {
+                i += 1;
+            }
+            let mut data = [0u8; 8];
+            let bytes = &mut data[..2 * (i - start)];
+            debug_assert!(start < i && i <= AS7331_RESULT_REGS.len());
+            self.i2c_read_bytes(AS7331_RESULT_REGS[start], bytes)?;
+            for (r, b) in raw[start..i].iter_mut().zip(bytes.chunks_exact(2)) {
+                *r = ((b[1] as u16) << 8) | (b[0] as u16);
+            }
+        }
+        Ok(raw)
+    }
+
+    pub fn read_all_data(&mut self) -> Result<[u16; 4], R::Error> {
+        let mut raw_data = [0u8; 8];
+        self.i2c_read_bytes(MeasReg::Temp, &mut raw_data)?;
+        Ok([
+            ((raw_data[1] as u16) << 8) | (raw_data[0] as u16),
+            ((raw_data[3] as u16) << 8) | (raw_data[2] as u16),
+            ((raw_data[5] as u16) << 8) | (raw_data[4] as u16),
+            ((raw_data[7] as u16) << 8) | (raw_data[6] as u16),
+        ])
+    }
+
+    /// TEMP, MRES1, MRES2 and MRES3 as raw bytes, each 16-bit result in the
+    /// requested byte order.
+    ///
+    /// The device sends little endian, [`Endian::Big`] swaps the two bytes of
+    /// every result.
+    pub fn read_all_data_bytes(&mut self, endian: Endian) -> Result<[u8; 8], R::Error> {
+        let mut raw_data = [0u8; 8];
+        self.i2c_read_bytes(MeasReg::Temp, &mut raw_data)?;
+        if endian == Endian::Big {
+            for pair in raw_data.chunks_exact_mut(2) {
+                pair.swap(0, 1);
+            }
+        }
+        Ok(raw_data)
+    }
+
+    /// Whether the current result is a genuine dark reading.
+    ///
+    /// All three UV channels at 0 counts can be a valid measurement in the
+    /// dark or under a cover, but also a device that is not measuring and
+    /// still holds its reset values. STATUS and the results are read in one
+    /// transfer: all-zero results with NDATA set and no overflow flag are
+    /// [`DarkReading::Dark`], all-zero results without a completed conversion
+    /// are [`DarkReading::NotMeasuring`]. Any non-zero channel is
+    /// [`DarkReading::Lit`].
+    pub fn is_dark_reading(&mut self) -> Result<DarkReading, R::Error> {
+        let mut data = [0u8; 10];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        if data[4..].iter().any(|&b| b != 0) {
+            return Ok(DarkReading::Lit);
+        }
+        let status = Status::from_byte(data[1]);
+        let overflow = status.adc_overflow || status.mres_overflow;
+        if !status.ndata || status.not_ready || overflow {
+            return Ok(DarkReading::NotMeasuring);
+        }
+        Ok(DarkReading::Dark)
+    }
+
+    /// Whether a conversion is running (NOTREADY status bit).
+    pub fn conversion_in_progress(&mut self) -> Result<bool, R::Error> {
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        Ok(data[1] & AS7331_STATUS_NOTREADY != 0)
+    }
+
+    /// [`wait_for_data_with`](Self::wait_for_data_with) waiting 1 ms between
+    /// polls with the FreeRTOS delay.
+    ///
+    /// `FreeRtos` rounds each delay up to a scheduler tick, so at the default
+    /// 100 Hz tick rate the timeout stretches by up to ten times.
+    #[cfg(feature = "esp-idf")]
+    pub fn wait_for_data(&mut self, timeout_ms: u32) -> Result<(), As7331Error<R::Error>> {
+        self.wait_for_data_with(timeout_ms, &mut FreeRtos)
+    }
+
+    /// Polls the status register until a result is available.
+    ///
+    /// In CMD mode the conversion starts with the SS bit, so this waits for
+    /// NOTREADY to clear. In CONT and SYND mode conversions follow each other
+    /// and NOTREADY is set most of the time, and in SYNS mode NOTREADY is clear
+    /// until the SYN edge arrives, so these wait for NDATA instead.
+    ///
+    /// Waits 1 ms between polls with `delay`, so the caller decides how the
+    /// CPU is yielded. `timeout_ms` counts these delays, not wall time: a
+    /// delay that rounds up to a scheduler tick stretches the timeout by the
+    /// tick length per poll. Use a delay with ms resolution, such as the
+    /// busy-waiting `esp_idf_hal::delay::Ets`, where the timeout has to hold.
+    pub fn wait_for_data_with<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<(), As7331Error<R::Error>> {
+        let mut budget_ms = timeout_ms;
+        self.poll_status(&mut budget_ms, delay).map(|_| ())
+    }
+
+    /// [`wait_for_data_with`](Self::wait_for_data_with) returning the STATUS byte that
+    /// signalled the result.
+    fn wait_for_status<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<u8, As7331Error<R::Error>> {
+        let mut budget_ms = timeout_ms;
+        self.poll_status(&mut budget_ms, delay)
+    }
+
+    /// Whether a result is available in the current measurement mode, from a
+    /// single STATUS read.
+    ///
+    /// Selects the flag the same way as
+    /// [`wait_for_data_with`](Self::wait_for_data_with): NOTREADY cleared in CMD mode,
+    /// NDATA set in CONT, SYNS and SYND mode.
+    pub fn measurement_complete(&mut self) -> Result<bool, As7331Error<R::Error>> {
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        Ok(self.result_ready(data[1]))
+    }
+
+    /// Checks the ready flag of the measurement mode in a STATUS byte.
+    fn result_ready(&self, status: u8) -> bool {
+        match self.config.mmode {
+            AS7331_CREG3_MMODE_CMD => status & AS7331_STATUS_NOTREADY == 0,
+            _ => status & AS7331_STATUS_NDATA != 0,
+        }
+    }
+
+    /// Status poll of [`wait_for_status`](Self::wait_for_status) that takes the
+    /// waited time off `budget_ms`, for loops sharing one timeout.
+    ///
+    /// Each poll takes 1 ms off the budget, whatever `delay` actually slept,
+    /// see [`wait_for_data_with`](Self::wait_for_data_with).
+    fn poll_status<D: DelayNs>(
+        &mut self,
+        budget_ms: &mut u32,
+        delay: &mut D,
+    ) -> Result<u8, As7331Error<R::Error>> {
+        loop {
+            let mut data = [0u8; 2];
+            self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+            if self.result_ready(data[1]) {
+                return Ok(data[1]);
+            }
+            if *budget_ms == 0 {
+                return Err(As7331Error::Timeout);
+            }
+            delay.delay_ms(1);
+            *budget_ms -= 1;
+        }
+    }
+
+    /// [`measure_blocking`](Self::measure_blocking) waiting with the
+    /// FreeRTOS delay.
+    #[cfg(feature = "esp-idf")]
+    pub fn measure(&mut self, timeout_ms: u32) -> Result<Measurement, As7331Error<R::Error>> {
+        self.measure_blocking(timeout_ms, &mut FreeRtos)
+    }
+
+    /// Triggers a one-shot conversion and returns the decoded result, waiting
+    /// for it with `delay`.
+    ///
+    /// Expects command mode (MMODE=CMD) and measurement state.
+    pub fn measure_blocking<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<Measurement, As7331Error<R::Error>> {
+        self.one_shot()?;
+        self.wait_for_data_with(timeout_ms, delay)?;
+        let raw = self.read_selected()?;
+        Ok(self.decode_selected(raw, self.read_selection))
+    }
+
+    /// Starts a one-shot conversion and returns without waiting.
+    ///
+    /// For super-loops that do other work in the meantime: poll
+    /// [`try_complete`](Self::try_complete) with the returned token until the
+    /// result is there. Expects command mode and measurement state.
+    pub fn trigger(&mut self) -> Result<PendingMeasurement, As7331Error<R::Error>> {
+        self.one_shot()?;
+        Ok(PendingMeasurement { _private: () })
+    }
+
+    /// Result of the conversion started by [`trigger`](Self::trigger), or
+    /// `WouldBlock` while it is still running.
+    ///
+    /// Each call reads STATUS once, see
+    /// [`measurement_complete`](Self::measurement_complete).
+    pub fn try_complete(
+        &mut self,
+        _token: &PendingMeasurement,
+    ) -> nb::Result<Measurement, As7331Error<R::Error>> {
+        if !self.measurement_complete()? {
+            return Err(nb::Error::WouldBlock);
+        }
+        let raw = self.read_selected().map_err(As7331Error::from)?;
+        Ok(self.decode_selected(raw, self.read_selection))
+    }
+
+    /// Repeated one-shot measurements in command mode, passing each to `f`
+    /// until it returns `false`.
+    ///
+    /// The next conversion is triggered right after the previous result is
+    /// read, before `f` runs, so the conversion overlaps with the processing
+    /// in `f`. The conversion triggered before the last `f` returns `false`
+    /// is waited for and discarded, leaving the device idle. `timeout_ms`
+    /// applies to each conversion, `delay` runs between the status polls.
+    pub fn pipelined_measure<D, F>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+        mut f: F,
+    ) -> Result<(), As7331Error<R::Error>>
+    where
+        D: DelayNs,
+        F: FnMut(&Measurement) -> bool,
+    {
+        self.one_shot()?;
+        loop {
+            self.wait_for_data_with(timeout_ms, delay)?;
+            let raw = self.read_selected()?;
+            self.one_shot()?;
+            let m = self.decode_selected(raw, self.read_selection);
+            if !f(&m) {
+                self.wait_for_data_with(timeout_ms, delay)?;
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads conversions in continuous mode and passes each to `f` until it
+    /// returns `false`.
+    ///
+    /// `timeout_ms` applies to each conversion, `delay` runs between the
+    /// status polls. The device has to be in measurement state with
+    /// MMODE=CONT (or SYND).
+    pub fn poll_continuous<D, F>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+        mut f: F,
+    ) -> Result<(), As7331Error<R::Error>>
+    where
+        D: DelayNs,
+        F: FnMut(&Measurement) -> bool,
+    {
+        loop {
+            let m = self.next_continuous(timeout_ms, delay)?;
+            if !f(&m) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Iterator over the results in continuous mode, see
+    /// [`poll_continuous`](Self::poll_continuous).
+    pub fn measurements<'d, D: DelayNs>(
+        &'d mut self,
+        timeout_ms: u32,
+        delay: &'d mut D,
+    ) -> Measurements<'d, R, D> {
+        self.measurements_decimated(timeout_ms, 1, delay)
+    }
+
+    /// Like [`measurements`](Self::measurements), but yields only every
+    /// `keep_every`-th result.
+    ///
+    /// The results in between are still read, so NDATA is cleared and the
+    /// device does not flag them as lost.
+    pub fn measurements_decimated<'d, D: DelayNs>(
+        &'d mut self,
+        timeout_ms: u32,
+        keep_every: u32,
+        delay: &'d mut D,
+    ) -> Measurements<'d, R, D> {
+        Measurements {
+            dev: self,
+            delay,
+            timeout_ms,
+            keep_every: keep_every.max(1),
+            stall_detection: false,
+        }
+    }
+
+    /// Endless stream of results in continuous mode.
+    ///
+    /// Reads STATUS and yields a result once NDATA is set, so the consumer
+    /// sets the pace. The I2C driver is blocking and the READY pin is not
+    /// used, so while no data is available the stream awaits a 1 ms `delay`
+    /// before the next STATUS read and the executor is free in between.
+    #[cfg(feature = "async")]
+    pub fn measurement_stream<'d, D>(
+        &'d mut self,
+        delay: D,
+    ) -> impl futures_core::Stream<Item = Result<Measurement, As7331Error<R::Error>>> + 'd
+    where
+        D: embedded_hal_async::delay::DelayNs + 'd,
+    {
+        futures_util::stream::unfold((self, delay), |(dev, mut delay)| async move {
+            let item = dev.next_continuous_async(&mut delay).await;
+            Some((item, (dev, delay)))
+        })
+    }
+
+    /// [`next_continuous`](Self::next_continuous) awaiting `delay` between
+    /// STATUS reads, without a timeout.
+    #[cfg(feature = "async")]
+    async fn next_continuous_async<D: embedded_hal_async::delay::DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement, As7331Error<R::Error>> {
+        loop {
+            let status = self.read_status_byte()?;
+            if status & AS7331_STATUS_NDATA != 0 {
+                return Ok(self.take_continuous(status)?);
+            }
+            delay.delay_ms(1).await;
+        }
+    }
+
+    /// Waits for and reads the next result in continuous mode, counting it
+    /// for [`completed_conversions`](Self::completed_conversions).
+    fn next_continuous<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<Measurement, As7331Error<R::Error>> {
+        let status = self.wait_for_status(timeout_ms, delay)?;
+        Ok(self.take_continuous(status)?)
+    }
+
+    /// Reads the result signalled by `status` and counts it, together with
+    /// one lost result if LDATA is set.
+    fn take_continuous(&mut self, status: u8) -> Result<Measurement, R::Error> {
+        let raw = self.read_all_data()?;
+        self.conversions_read = self.conversions_read.wrapping_add(1);
+        if status & AS7331_STATUS_LDATA != 0 {
+            self.conversions_missed = self.conversions_missed.wrapping_add(1);
+        }
+        Ok(self.decode(raw))
+    }
+
+    /// Conversions completed by the device while in
+    /// [`poll_continuous`](Self::poll_continuous) or
+    /// [`measurements`](Self::measurements), read or not.
+    ///
+    /// The device has no conversion counter. This counts every result read
+    /// plus one for each result flagged as overwritten by LDATA. LDATA does not
+    /// tell how many results were lost, so the missed count is a lower bound.
+    pub fn completed_conversions(&self) -> u32 {
+        self.conversions_read.wrapping_add(self.conversions_missed)
+    }
+
+    /// Conversions per second achieved in continuous mode, counted over
+    /// `window_ms`.
+    ///
+    /// Polls STATUS about every millisecond with `delay` and reads each new
+    /// result, counting results flagged as lost by LDATA as well. The window
+    /// is timed with `now_us`, a monotonic clock in us such as
+    /// `esp_timer_get_time`, so neither the bus time of the polls nor a
+    /// delay rounded up to the scheduler tick skews the rate.
+    pub fn measured_sample_rate<D, C>(
+        &mut self,
+        window_ms: u32,
+        delay: &mut D,
+        mut now_us: C,
+    ) -> Result<f32, As7331Error<R::Error>>
+    where
+        D: DelayNs,
+        C: FnMut() -> u64,
+    {
+        if window_ms == 0 {
+            return Err(As7331Error::InvalidConfig {
+                field: "window_ms",
+                value: 0,
+            });
+        }
+        let window_us = window_ms as u64 * 1000;
+        let start = now_us();
+        let mut conversions = 0u32;
+        let elapsed_us = loop {
+            let status = self.read_status_byte()?;
+            if status & AS7331_STATUS_NDATA != 0 {
+                self.read_all_data()?;
+                conversions += 1;
+                if status & AS7331_STATUS_LDATA != 0 {
+                    conversions += 1;
+                }
+            }
+            let elapsed_us = now_us().wrapping_sub(start);
+            if elapsed_us >= window_us {
+                break elapsed_us;
+            }
+            delay.delay_ms(1);
+        };
+        Ok(conversions as f32 * 1_000_000.0 / elapsed_us as f32)
+    }
+
+    /// Conversions detected as overwritten before they were read.
+    pub fn missed_conversions(&self) -> u32 {
+        self.conversions_missed
+    }
+
+    pub fn reset_conversion_count(&mut self) {
+        self.conversions_read = 0;
+        self.conversions_missed = 0;
+    }
+
+    /// [`poll_continuous`](Self::poll_continuous) that also passes the
+    /// irradiance smoothed by `smoother` to `f`.
+    pub fn poll_continuous_smoothed<D, F>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+        smoother: &mut EmaSmoother,
+        mut f: F,
+    ) -> Result<(), As7331Error<R::Error>>
+    where
+        D: DelayNs,
+        F: FnMut(&Measurement, [f32; 3]) -> bool,
+    {
+        self.poll_continuous(timeout_ms, delay, |m| f(m, smoother.update(m)))
+    }
+
+    /// Samples the die temperature until `samples` consecutive readings are
+    /// within `threshold_milli_c` of each other.
+    ///
+    /// Returns `false` if the temperature has not settled within `timeout_ms`.
+    /// In CMD mode a conversion is triggered for each sample, in the other
+    /// modes the next result is awaited, polling with `delay`. The whole
+    /// result block is read each time, which clears NDATA for the next one.
+    pub fn temperature_stable<D: DelayNs>(
+        &mut self,
+        samples: u8,
+        threshold_milli_c: i32,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<bool, As7331Error<R::Error>> {
+        let mut budget_ms = timeout_ms;
+        let mut previous: Option<i32> = None;
+        let mut stable = 0;
+        loop {
+            if self.config.mmode == AS7331_CREG3_MMODE_CMD {
+                self.one_shot()?;
+            }
+            match self.poll_status(&mut budget_ms, delay) {
+                Ok(_) => {}
+                Err(As7331Error::Timeout) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            let raw = self.read_all_data()?;
+            let t = convert::temperature_milli_celsius(raw[0]);
+            stable = match previous {
+                Some(p) if (t - p).abs() < threshold_milli_c => stable + 1,
+                _ => 1,
+            };
+            previous = Some(t);
+            if stable >= samples {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Sets up the fastest conversion for temperature-only use: CMD mode
+    /// and 1 ms integration time, the other settings are kept.
+    ///
+    /// The AS7331 cannot switch off the UV channels, they still convert and
+    /// their results are just not read. The shortest integration time makes
+    /// the device draw the active current for 1 ms per sample instead of
+    /// 64 ms at the reset default, see [`estimate_current_ua`]. The device is
+    /// left in measurement mode without starting a conversion.
+    pub fn configure_temperature_only(&mut self) -> Result<(), As7331Error<R::Error>> {
+        let cfg = Config {
+            mmode: AS7331_CREG3_MMODE_CMD,
+            time: AS7331_CREG1_TIME_1,
+            ..self.config
+        };
+        self.set_configuration_mode()?;
+        self.apply(&cfg)?;
+        self.i2c_write_cmd(ConfigReg::Osr, AS7331_OSR_DOS_MEASUREMENT)?;
+        Ok(())
+    }
+
+    /// Sets up the shortest one-shot: CMD mode, 1 ms TIME setting at the
+    /// 8.192 MHz clock, no standby and no break; gain, divider and EDGES are
+    /// kept.
+    ///
+    /// A conversion then takes about 0.125 ms, see
+    /// [`measurement_duration_ms`](Self::measurement_duration_ms), at the
+    /// cost of a 1024-count full scale. The device is left in measurement
+    /// mode without starting a conversion.
+    pub fn configure_low_latency(&mut self) -> Result<(), As7331Error<R::Error>> {
+        let cfg = Config {
+            mmode: AS7331_CREG3_MMODE_CMD,
+            time: AS7331_CREG1_TIME_1,
+            cclk: AS7331_CREG3_CCLK_8192,
+            sb: AS7331_CREG3_SB_OFF,
+            break_time: 0,
+            ..self.config
+        };
+        self.set_configuration_mode()?;
+        self.apply(&cfg)?;
+        self.i2c_write_cmd(ConfigReg::Osr, AS7331_OSR_DOS_MEASUREMENT)?;
+        Ok(())
+    }
+
+    /// Duration of one conversion in ms at the stored config, the integration
+    /// time at the configured clock. Bus transfers are not included.
+    pub fn measurement_duration_ms(&self) -> f32 {
+        integration_time_ms(self.config.time, self.config.cclk)
+    }
+
+    /// Triggers a conversion and reads only the TEMP register, in Celsius.
+    ///
+    /// Expects command mode, e.g. after
+    /// [`configure_temperature_only`](Self::configure_temperature_only).
+    pub fn measure_temperature_only<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<f32, As7331Error<R::Error>> {
+        self.one_shot()?;
+        self.wait_for_data_with(timeout_ms, delay)?;
+        Ok(temperature_celsius(self.read_temp_data()?))
+    }
+
+    /// Arms a measurement that starts on the next SYN edge and returns its result.
+    ///
+    /// Switches to SYNS mode first if needed. The integration time is the
+    /// configured TIME. Returns [`As7331Error::Timeout`] if no falling SYN
+    /// edge arrives and completes within `timeout_ms`; `delay` runs between
+    /// the status polls.
+    pub fn measure_syns<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<Measurement, As7331Error<R::Error>> {
+        if self.config.mmode != AS7331_CREG3_MMODE_SYNS {
+            self.set_configuration_mode()?;
+            self.set_measurement_mode_mmode(MeasurementMode::SynStart)?;
+            self.i2c_write_cmd(ConfigReg::Osr, AS7331_OSR_DOS_MEASUREMENT)?;
+        }
+        self.one_shot()?;
+        self.wait_for_data_with(timeout_ms, delay)?;
+        let raw = self.read_all_data()?;
+        Ok(self.decode(raw))
+    }
+
+    /// Reads the current results together with advice on the gain, leaving
+    /// the reconfiguration to the caller.
+    ///
+    /// STATUS and the results are read in one transfer. An ADC or result
+    /// overflow, or a channel above the auto-ranging window, asks for a lower
+    /// gain; all channels below the window ask for a higher one. The window
+    /// scales with the full-scale count of the integration time. No change is
+    /// advised beyond the gain limits.
+    pub fn read_with_range_hint(
+        &mut self,
+    ) -> Result<(Measurement, RangeHint), As7331Error<R::Error>> {
+        let (status, raw) = self.read_status_block()?;
+        let (high, low) = self.autorange_window();
+        let overflow = status & (AS7331_STATUS_ADCOF | AS7331_STATUS_MRESOF) != 0;
+        let peak = raw[1].max(raw[2]).max(raw[3]);
+        let gain = self.config.gain;
+        let hint = if (overflow || peak > high) && gain < AS7331_CREG1_GAIN_1 {
+            RangeHint::DecreaseGain
+        } else if !overflow && peak < low && gain > AS7331_CREG1_GAIN_2048 {
+            RangeHint::IncreaseGain
+        } else {
+            RangeHint::Ok
+        };
+        Ok((self.decode(raw), hint))
+    }
+
+    /// UV index from a single measurement at the current gain.
+    pub fn uv_index<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<f32, As7331Error<R::Error>> {
+        let m = self.measure_blocking(timeout_ms, delay)?;
+        Ok(uv_index_from_irradiance(m.uva, m.uvb))
+    }
+
+    /// Fraction of a minimal erythemal dose received over `duration_s`, see
+    /// [`med_fraction`].
+    ///
+    /// Reads the current results once and assumes the irradiance stays
+    /// constant for the whole duration, so for a varying sky the caller has
+    /// to sum up short windows.
+    pub fn sun_exposure_med(&mut self, duration_s: f32) -> Result<f32, As7331Error<R::Error>> {
+        let raw = self.read_all_data()?;
+        let m = self.decode(raw);
+        Ok(med_fraction(m.uva, m.uvb, duration_s))
+    }
+
+    /// UV index from an auto-ranged measurement.
+    ///
+    /// The gain is lowered while UVA or UVB is above 7/8 of the full-scale
+    /// count or overflowed and raised while both are below 7/16 of it. After at most one step
+    /// per gain setting the best unsaturated reading is used, or the reading
+    /// at the lowest gain if even that saturates. `timeout_ms` applies to each
+    /// conversion. The new gain is kept in the device afterwards.
+    pub fn uv_index_autorange<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<f32, As7331Error<R::Error>> {
+        let mut iterations = AS7331_AUTORANGE_MAX_ITERATIONS;
+        let m = self.autorange_by(timeout_ms, &mut iterations, delay, |m| {
+            m.raw[1].max(m.raw[2])
+        })?;
+        Ok(uv_index_from_irradiance(m.uva, m.uvb))
+    }
+
+    /// Irradiance of UVA, UVB and UVC, each auto-ranged on its own.
+    ///
+    /// Ranges the gain for UVA, then continues from that gain for UVB and UVC,
+    /// so each channel is read at the gain that suits it best. All three share
+    /// a budget of three times the single-channel iteration limit. The gain
+    /// found for UVC is kept in the device afterwards.
+    pub fn measure_per_channel_autorange<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<[f32; 3], As7331Error<R::Error>> {
+        let mut iterations = 3 * AS7331_AUTORANGE_MAX_ITERATIONS;
+        let uva = self
+            .autorange_by(timeout_ms, &mut iterations, delay, |m| m.raw[1])?
+            .uva;
+        let uvb = self
+            .autorange_by(timeout_ms, &mut iterations, delay, |m| m.raw[2])?
+            .uvb;
+        let uvc = self
+            .autorange_by(timeout_ms, &mut iterations, delay, |m| m.raw[3])?
+            .uvc;
+        Ok([uva, uvb, uvc])
+    }
+
+    /// Steps the gain until `peak` of a measurement is inside the
+    /// auto-ranging window, taking each measurement off `iterations`.
+    ///
+    /// The window scales with the full-scale count of the integration time,
+    /// and an ADC or result overflow counts as saturated.
+    ///
+    /// Returns the last measurement inside the window, or the best one seen
+    /// when the gain limits or the iteration budget are reached. The first
+    /// measurement after each gain change may still carry the old gain, so it
+    /// is discarded without counting towards `iterations`.
+    fn autorange_by<D, F>(
+        &mut self,
+        timeout_ms: u32,
+        iterations: &mut usize,
+        delay: &mut D,
+        peak: F,
+    ) -> Result<Measurement, As7331Error<R::Error>>
+    where
+        D: DelayNs,
+        F: Fn(&Measurement) -> u16,
+    {
+        let (high, low) = self.autorange_window();
+        let mut best = None;
+        let mut gain_changed = false;
+        loop {
+            if gain_changed {
+                self.measure_blocking(timeout_ms, delay)?;
+            }
+            gain_changed = true;
+            self.one_shot()?;
+            self.wait_for_data_with(timeout_ms, delay)?;
+            let (status, raw) = self.read_status_block()?;
+            let m = self.decode(raw);
+            *iterations = iterations.saturating_sub(1);
+            let p = peak(&m);
+            let gain = self.config.gain;
+            if p > high || status & (AS7331_STATUS_ADCOF | AS7331_STATUS_MRESOF) != 0 {
+                if gain >= AS7331_CREG1_GAIN_1 || *iterations == 0 {
+                    return Ok(best.unwrap_or(m));
+                }
+                self.set_gain(gain + 1)?;
+            } else {
+                if p >= low || gain == AS7331_CREG1_GAIN_2048 || *iterations == 0 {
+                    return Ok(m);
+                }
+                best = Some(m);
+                self.set_gain(gain - 1)?;
+            }
+        }
+    }
+
+    /// Upper and lower bound of the auto-ranging window at the stored
+    /// integration time and divider.
+    fn autorange_window(&self) -> (u16, u16) {
+        let full_scale = self.full_scale_counts() as u32;
+        let scale = |bound: u16| (full_scale * bound as u32 / 0x10000) as u16;
+        (scale(AS7331_AUTORANGE_HIGH), scale(AS7331_AUTORANGE_LOW))
+    }
+
+    /// Reads STATUS together with TEMP, MRES1, MRES2 and MRES3 in one
+    /// transfer.
+    fn read_status_block(&mut self) -> Result<(u8, [u16; 4]), R::Error> {
+        let mut data = [0u8; 10];
+        self.i2c_read_bytes(MeasReg::Status, &mut data)?;
+        let word = |i: usize| ((data[i + 1] as u16) << 8) | (data[i] as u16);
+        Ok((data[1], [word(2), word(4), word(6), word(8)]))
+    }
+
+    /// Writes the header line matching [`write_csv_row`](Self::write_csv_row).
+    #[cfg(feature = "embedded-io")]
+    pub fn write_csv_header<W: embedded_io::Write>(&self, w: &mut W) -> Result<(), W::Error> {
+        w.write_all(b"temp_c,uva,uvb,uvc\n")
+    }
+
+    /// Writes `m` as one CSV line: temperature in Celsius, then UVA, UVB and
+    /// UVC irradiance in uW/cm^2.
+    #[cfg(feature = "embedded-io")]
+    pub fn write_csv_row<W: embedded_io::Write>(
+        &self,
+        m: &Measurement,
+        w: &mut W,
+    ) -> Result<(), W::Error> {
+        use core::fmt::Write;
+        let mut out = CsvWriter {
+            inner: w,
+            error: Ok(()),
+        };
+        let _ = writeln!(
+            out,
+            "{:.2},{:.3},{:.3},{:.3}",
+            m.temperature, m.uva, m.uvb, m.uvc
+        );
+        out.error
+    }
+
+    /// One-line summary of `m` for small displays, e.g.
+    /// `UVA:12.3 UVB:4.5 UVC:0.2 T:28C`.
+    ///
+    /// Irradiance in uW/cm^2 with one decimal, temperature in whole Celsius.
+    /// Values too long for the 64 bytes are cut off.
+    #[cfg(feature = "heapless")]
+    pub fn summary_line(&self, m: &Measurement) -> heapless::String<64> {
+        use core::fmt::Write;
+        let mut line = heapless::String::new();
+        let _ = write!(
+            line,
+            "UVA:{:.1} UVB:{:.1} UVC:{:.1} T:{:.0}C",
+            m.uva, m.uvb, m.uvc, m.temperature
+        );
+        line
+    }
+
+    /// Converts a result block, applying calibration and temperature
+    /// compensation.
+    fn decode(&self, raw: [u16; 4]) -> Measurement {
+        self.decode_selected(raw, ReadSelection::ALL)
+    }
+
+    /// [`decode`](Self::decode) of a block read with `selection`.
+    fn decode_selected(&self, raw: [u16; 4], selection: ReadSelection) -> Measurement {
+        let temperature = selection
+            .contains(ReadSelection::TEMP)
+            .then(|| temperature_celsius(raw[0]));
+        let uv = convert::counts_to_irradiance([raw[1], raw[2], raw[3]], self.lsb());
+        let cal = self.calibration.factors(temperature);
+        Measurement {
+            raw,
+            temperature: temperature.unwrap_or(f32::NAN),
+            uva: uv[0] * cal[0],
+            uvb: uv[1] * cal[1],
+            uvc: uv[2] * cal[2],
+        }
+    }
+
+    /// Irradiance per count in uW/cm^2 for UVA, UVB, UVC at the stored config.
+    ///
+    /// Includes the divider and the window transmission.
+    fn lsb(&self) -> [f32; 3] {
+        let lsb = convert::resolution(&self.config);
+        let t = self.window_transmission;
+        [lsb[0] / t[0], lsb[1] / t[1], lsb[2] / t[2]]
+    }
+
+    /// Irradiance in uW/cm^2 at the highest result for UVA, UVB, UVC at the
+    /// stored config, see [`convert::full_scale_counts`].
+    pub fn full_scale_range(&self) -> [f32; 3] {
+        convert::full_scale_range(self.lsb(), self.full_scale_counts())
+    }
+
+    fn full_scale_counts(&self) -> u16 {
+        convert::full_scale_counts(self.config.time, self.config.divider)
+    }
+
+    /// Highest irradiance in uW/cm^2 the reported UVA, UVB, UVC values can
+    /// reach at the stored config, for alarm thresholds.
+    ///
+    /// This is [`full_scale_range`](Self::full_scale_range) with the calibration scale applied, at the reference temperature.
+    /// Readings at or above it are clipped and unreliable.
+    pub fn max_measurable_irradiance(&self) -> [f32; 3] {
+        let fsr = self.full_scale_range();
+        let cal = self.calibration.factors(None);
+        [fsr[0] * cal[0], fsr[1] * cal[1], fsr[2] * cal[2]]
+    }
+
+    /// Irradiance in uW/cm^2 of one count for UVA, UVB, UVC at the stored
+    /// config, i.e. [`full_scale_range`](Self::full_scale_range) divided by the
+    /// full-scale count.
+    ///
+    /// This is the finest irradiance step the current gain, integration time
+    /// and divider can resolve.
+    pub fn resolution(&self) -> [f32; 3] {
+        self.lsb()
+    }
+
+    /// Uncertainty in uW/cm^2 of the UVA, UVB and UVC irradiance of `m`.
+    ///
+    /// Adds half a count of quantization, the count noise and the relative
+    /// noise of the [`NoiseModel`] at the stored config. The terms are summed
+    /// rather than added in quadrature, so this is an upper bound. Calibration
+    /// is applied like in `m`.
+    pub fn measurement_uncertainty(&self, m: &Measurement) -> [f32; 3] {
+        let lsb = self.lsb();
+        let temperature = Some(m.temperature).filter(|t| !t.is_nan());
+        let cal = self.calibration.factors(temperature);
+        let value = [m.uva, m.uvb, m.uvc];
+        let mut u = [0f32; 3];
+        for i in 0..3 {
+            let quantum = lsb[i] * cal[i];
+            u[i] = quantum * (0.5 + self.noise.counts[i]) + value[i] * self.noise.relative[i];
+        }
+        u
+    }
+
+    /// Fraction of the full-scale range (0.0 to 1.0) used by the current
+    /// UVA, UVB and UVC results.
+    ///
+    /// This is the irradiance relative to [`full_scale_range`](Self::full_scale_range),
+    /// i.e. the count relative to the full-scale count of the integration
+    /// time. Values near 1.0 warn of saturation, values near 0.0 leave room
+    /// for a higher gain.
+    pub fn headroom(&mut self) -> Result<[f32; 3], As7331Error<R::Error>> {
+        let raw = self.read_uv_only()?;
+        let max = self.full_scale_counts() as f32;
+        Ok([
+            raw[0] as f32 / max,
+            raw[1] as f32 / max,
+            raw[2] as f32 / max,
+        ])
+    }
+
+    /// Reads the current results in the form that fits the measurement mode.
+    ///
+    /// In SYND mode the result block is read together with OUTCONV in one
+    /// transfer, which gives the length of the measurement window; the read
+    /// selection does not apply there. The other modes return the plain
+    /// [`Measurement`] of the selected registers. Does not wait for new data.
+    pub fn read(&mut self) -> Result<ReadResult, As7331Error<R::Error>> {
+        if self.config.mmode != AS7331_CREG3_MMODE_SYND {
+            let raw = self.read_selected()?;
+            let m = self.decode_selected(raw, self.read_selection);
+            return Ok(ReadResult::Measurement(m));
+        }
+        let mut data = [0u8; 12];
+        self.i2c_read_bytes(MeasReg::Temp, &mut data)?;
+        let word = |i: usize| ((data[i + 1] as u16) << 8) | (data[i] as u16);
+        let raw = [word(0), word(2), word(4), word(6)];
+        let conversions = (data[10] as u32) << 16 | word(8) as u32;
+        Ok(ReadResult::Synd {
+            measurement: self.decode(raw),
+            conversions,
+        })
+    }
+
+    /// Duration in ms of the last conversion, from the OUTCONV counter.
+    ///
+    /// The device counts the conversion clock during every measurement, so
+    /// this works in any measurement mode. A value that differs from the
+    /// configured integration time points to a clock problem.
+    pub fn last_conversion_time_ms(&mut self) -> Result<f32, As7331Error<R::Error>> {
+        let mut data = [0u8; 4];
+        self.i2c_read_bytes(MeasReg::OutconvL, &mut data)?;
+        let count = (data[2] as u32) << 16 | (data[1] as u32) << 8 | data[0] as u32;
+        Ok(convert::outconv_to_ms(count, self.config.cclk))
+    }
+
+    /// Reads the current results with temperature in Celsius and irradiance
+    /// in W/m^2.
+    ///
+    /// The irradiance includes the divider, window transmission, calibration
+    /// and temperature compensation.
+    pub fn read_si(&mut self) -> Result<SiReading, As7331Error<R::Error>> {
+        let raw = self.read_all_data()?;
+        let m = self.decode(raw);
+        Ok(SiReading {
+            temperature_c: m.temperature,
+            uva_w_m2: m.uva * AS7331_UW_CM2_TO_W_M2,
+            uvb_w_m2: m.uvb * AS7331_UW_CM2_TO_W_M2,
+            uvc_w_m2: m.uvc * AS7331_UW_CM2_TO_W_M2,
+        })
+    }
+
+    /// Reads the current UVA, UVB, UVC irradiance in uW/cm^2 multiplied by
+    /// the correction matrix, see
+    /// [`set_correction_matrix`](Self::set_correction_matrix).
+    ///
+    /// The passbands of the channels overlap; a matrix characterized against
+    /// a reference spectrometer removes the cross-talk.
+    pub fn read_uv_corrected(&mut self) -> Result<[f32; 3], As7331Error<R::Error>> {
+        let raw = self.read_all_data()?;
+        let m = self.decode(raw);
+        let uv = [m.uva, m.uvb, m.uvc];
+        Ok(self
+            .correction
+            .map(|row| row[0] * uv[0] + row[1] * uv[1] + row[2] * uv[2]))
+    }
+
+    /// [`read_si`](Self::read_si) as `uom` quantities, so units are checked
+    /// at compile time.
+    #[cfg(feature = "uom")]
+    pub fn read_measurement_uom(&mut self) -> Result<UomMeasurement, As7331Error<R::Error>> {
+        use uom::si::f32::{HeatFluxDensity, ThermodynamicTemperature};
+        use uom::si::heat_flux_density::watt_per_square_meter;
+        use uom::si::thermodynamic_temperature::degree_celsius;
+        let si = self.read_si()?;
+        Ok(UomMeasurement {
+            temperature: ThermodynamicTemperature::new::<degree_celsius>(si.temperature_c),
+            uva: HeatFluxDensity::new::<watt_per_square_meter>(si.uva_w_m2),
+            uvb: HeatFluxDensity::new::<watt_per_square_meter>(si.uvb_w_m2),
+            uvc: HeatFluxDensity::new::<watt_per_square_meter>(si.uvc_w_m2),
+        })
+    }
+
+    /// Reads the current results as photon flux in photons/(cm^2 s) for UVA,
+    /// UVB and UVC.
+    ///
+    /// Each channel is treated as monochromatic at its effective wavelength,
+    /// see [`set_effective_wavelengths`](Self::set_effective_wavelengths).
+    pub fn read_photon_flux(&mut self) -> Result<[f32; 3], As7331Error<R::Error>> {
+        let raw = self.read_all_data()?;
+        let m = self.decode(raw);
+        let wl = self.wavelengths_nm;
+        Ok([
+            photon_flux(m.uva, wl[0]),
+            photon_flux(m.uvb, wl[1]),
+            photon_flux(m.uvc, wl[2]),
+        ])
+    }
+
+    /// Reads the UV results as irradiance in uW/cm^2 that does not depend on
+    /// the integration time, see [`convert::normalized_irradiance`].
+    ///
+    /// The conversion uses the stored config, so settings have to be changed
+    /// through this driver for a logged series to stay continuous. Window
+    /// transmission and calibration scale are applied, temperature
+    /// compensation is not.
+    pub fn normalized_irradiance(&mut self) -> Result<[f32; 3], As7331Error<R::Error>> {
+        let raw = self.read_uv_only()?;
+        let uv = convert::counts_to_irradiance(raw, self.lsb());
+        let cal = self.calibration.factors(None);
+        Ok([uv[0] * cal[0], uv[1] * cal[1], uv[2] * cal[2]])
+    }
+
+    /// Reads the current results and derives everything a spectrum report
+    /// needs from them, see [`SpectrumReport`].
+    ///
+    /// Photon flux uses the effective wavelengths of
+    /// [`set_effective_wavelengths`](Self::set_effective_wavelengths).
+    pub fn spectrum_report(&mut self) -> Result<SpectrumReport, As7331Error<R::Error>> {
+        let raw = self.read_all_data()?;
+        let m = self.decode(raw);
+        let wl = self.wavelengths_nm;
+        Ok(SpectrumReport {
+            measurement: m,
+            photon_flux: [
+                photon_flux(m.uva, wl[0]),
+                photon_flux(m.uvb, wl[1]),
+                photon_flux(m.uvc, wl[2]),
+            ],
+            ratios: ChannelRatios::from_measurement(&m),
+            config: self.config,
+        })
+    }
+
+    /// Reads the current results as ratios between the channels, e.g. to
+    /// characterize the spectrum of a light source.
+    ///
+    /// The ratios use the calibrated irradiance, so they do not depend on
+    /// the gain or integration time.
+    pub fn channel_ratios(&mut self) -> Result<ChannelRatios, As7331Error<R::Error>> {
+        let raw = self.read_all_data()?;
+        Ok(ChannelRatios::from_measurement(&self.decode(raw)))
+    }
+
+    /// Reads the result block together with the stored gain, time, divider
+    /// and clock settings.
+    pub fn read_raw_record(&mut self) -> Result<RawRecord, R::Error> {
+        let raw = self.read_all_data()?;
+        Ok(RawRecord {
+            raw,
+            gain: self.config.gain,
+            time: self.config.time,
+            divider: self.config.divider.bits(),
+            cclk: self.config.cclk,
+        })
+    }
+
+    /// Reads temperature and all UV channels in one burst and scales them
+    /// with integer arithmetic only.
+    pub fn read_scaled_fixed(&mut self) -> Result<ScaledReading, As7331Error<R::Error>> {
+        let raw = self.read_all_data()?;
+        let nw = convert::counts_to_nw_fixed([raw[1], raw[2], raw[3]], &self.config);
+        Ok(ScaledReading {
+            temperature_mc: convert::temperature_milli_celsius(raw[0]),
+            uva_nw: nw[0],
+            uvb_nw: nw[1],
+            uvc_nw: nw[2],
+        })
+    }
+
+    /// Reads MRES1..MRES3 in one burst, without the temperature register.
+    ///
+    /// A single 6-byte transfer starting at MRES1, so UV-only applications
+    /// need neither the temperature-inclusive block of
+    /// [`read_all_data`](Self::read_all_data) nor three separate reads.
+    pub fn read_uv_only(&mut self) -> Result<[u16; 3], R::Error> {
+        let mut raw_data = [0u8; 6];
+        self.i2c_read_bytes(MeasReg::Mres1, &mut raw_data)?;
+        Ok([
+            ((raw_data[1] as u16) << 8) | (raw_data[0] as u16),
+            ((raw_data[3] as u16) << 8) | (raw_data[2] as u16),
+            ((raw_data[5] as u16) << 8) | (raw_data[4] as u16),
+        ])
+    }
+
+    /// Like [`measure_blocking`](Self::measure_blocking), but keeps the UV data if only the
+    /// temperature read fails.
+    ///
+    /// The UV registers are read first; a failing temperature read is logged
+    /// and reported as `None`, in which case no temperature compensation is
+    /// applied.
+    pub fn measure_uv<D: DelayNs>(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut D,
+    ) -> Result<UvMeasurement, As7331Error<R::Error>> {
+        self.one_shot()?;
+        self.wait_for_data_with(timeout_ms, delay)?;
+        let raw = self.read_uv_only()?;
+        let temperature = match self.read_temp_data() {
+            Ok(t) => Some(temperature_celsius(t)),
+            Err(e) => {
+                if self.tracing {
+                    debug!("AS7331 temperature read failed: {:?}", e);
+                }
+                None
+            }
+        };
+        let uv = convert::counts_to_irradiance(raw, self.lsb());
+        let cal = self.calibration.factors(temperature);
+        Ok(UvMeasurement {
+            raw,
+            temperature,
+            uva: uv[0] * cal[0],
+            uvb: uv[1] * cal[1],
+            uvc: uv[2] * cal[2],
+        })
+    }
+
+    /// Reads the current results register by register, keeping every value
+    /// that could be read.
+    ///
+    /// Each of TEMP, MRES1, MRES2 and MRES3 is its own transfer, so a failing
+    /// read only loses that value. Temperature compensation is applied if the
+    /// temperature read succeeded. Does not wait for new data.
+    pub fn read_detailed(&mut self) -> DetailedReading<R::Error> {
+        let temperature = self.read_temp_data().map(temperature_celsius);
+        let uva = self.read_uv_a_data();
+        let uvb = self.read_uv_b_data();
+        let uvc = self.read_uv_c_data();
+        let lsb = self.lsb();
+        let cal = self.calibration.factors(temperature.as_ref().ok().copied());
+        let scale = |i: usize| lsb[i] * cal[i];
+        DetailedReading {
+            temperature: temperature.map_err(As7331Error::I2c),
+            uva: uva.map(|c| c as f32 * scale(0)).map_err(As7331Error::I2c),
+            uvb: uvb.map(|c| c as f32 * scale(1)).map_err(As7331Error::I2c),
+            uvc: uvc.map(|c| c as f32 * scale(2)).map_err(As7331Error::I2c),
+        }
+    }
+
+    /// [`RegisterAccess::read_regs`] repeated up to the retry count.
+    fn bus_read(&mut self, reg: u8, data: &mut [u8]) -> Result<(), R::Error> {
+        let mut retries = self.retry_count;
+        loop {
+            match self.i2c.read_regs(self.addr, reg, data) {
+                Err(e) if retries > 0 => {
+                    if self.tracing {
+                        debug!("I2C read 0x{:02X} failed: {:?}, retrying", reg, e);
+                    }
+                    retries -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// [`RegisterAccess::write_reg`] repeated up to the retry count.
+    fn bus_write(&mut self, reg: u8, val: u8) -> Result<(), R::Error> {
+        let mut retries = self.retry_count;
+        loop {
+            match self.i2c.write_reg(self.addr, reg, val) {
+                Err(e) if retries > 0 => {
+                    if self.tracing {
+                        debug!("I2C write 0x{:02X} failed: {:?}, retrying", reg, e);
+                    }
+                    retries -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn i2c_write_read_cmd(&mut self, reg: ConfigReg, data: &mut [u8]) -> Result<(), R::Error> {
+        let addr = reg.addr();
+        match self.bus_read(addr, data) {
+            Ok(_) if self.tracing => debug!(
+                "I2C_WRITE_READ - ADDR: 0x{:02X} - READ: 0x{:02X}",
+                addr, data[0]
+            ),
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    fn i2c_read_bytes(&mut self, reg: impl Register, data: &mut [u8]) -> Result<(), R::Error> {
+        let addr = reg.addr();
+        match self.bus_read(addr, data) {
+            Ok(_) if self.tracing => {
+                debug!("I2C_READ_BYTES - ADDR: 0x{:02X} - DATA {:?}", addr, data)
+            }
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    fn i2c_write_cmd(&mut self, reg: ConfigReg, cmd: u8) -> Result<(), R::Error> {
+        let addr = reg.addr();
+        match self.bus_write(addr, cmd) {
+            Ok(_) if self.tracing => {
+                debug!("I2C_WRITE - ADDR: 0x{:02X} - DATa: 0x{:02X}", addr, cmd)
+            }
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        if reg == ConfigReg::Osr {
+            self.track_dos(cmd);
+        }
+        Ok(())
+    }
+
+    /// Records the device state selected by an OSR write; DOS=NOP keeps it.
+    ///
+    /// A software reset returns the device to configuration mode with the
+    /// reset values.
+    fn track_dos(&mut self, osr: u8) {
+        if osr & (AS7331_OSR_SW_RES_ON << 3) != 0 {
+            self.dos = AS7331_OSR_DOS_CONFIGURATION;
+            self.config = Config::default();
+            return;
+        }
+        let dos = osr & 0x07;
+        if dos == AS7331_OSR_DOS_CONFIGURATION || dos == AS7331_OSR_DOS_MEASUREMENT {
+            self.dos = dos;
+        }
+    }
+
+    /// Reads a configuration register.
+    ///
+    /// Fails with [`As7331Error::WrongMode`] outside configuration mode, where
+    /// the address would select a measurement register instead. OSR can be
+    /// read in both states. The state is the one last written through this
+    /// driver (configuration mode after [`new`](Self::new)).
+    pub fn read_config_reg(&mut self, reg: ConfigReg) -> Result<u8, As7331Error<R::Error>> {
+        if reg != ConfigReg::Osr && self.dos != AS7331_OSR_DOS_CONFIGURATION {
+            return Err(As7331Error::WrongMode);
+        }
+        let mut data = [0u8; 1];
+        self.i2c_write_read_cmd(reg, &mut data)?;
+        Ok(data[0])
+    }
+
+    /// Writes a configuration register, see [`read_config_reg`](Self::read_config_reg).
+    pub fn write_config_reg(
+        &mut self,
+        reg: ConfigReg,
+        val: u8,
+    ) -> Result<(), As7331Error<R::Error>> {
+        if reg != ConfigReg::Osr && self.dos != AS7331_OSR_DOS_CONFIGURATION {
+            return Err(As7331Error::WrongMode);
+        }
+        self.i2c_write_cmd(reg, val)?;
+        Ok(())
+    }
+
+    /// Reads a 16-bit measurement register.
+    ///
+    /// Fails with [`As7331Error::WrongMode`] outside measurement mode.
+    pub fn read_meas_reg(&mut self, reg: MeasReg) -> Result<u16, As7331Error<R::Error>> {
+        if self.dos != AS7331_OSR_DOS_MEASUREMENT {
+            return Err(As7331Error::WrongMode);
+        }
+        let mut data = [0u8; 2];
+        self.i2c_read_bytes(reg, &mut data)?;
+        Ok(((data[1] as u16) << 8) | (data[0] as u16))
+    }
+
+    pub fn power_up(&mut self) -> Result<(), R::Error> {
+        let data = [0u8; 22];
+        //self.i2c_write_read_cmd(ConfigReg::Osr, &mut data)?;
+        self.i2c_write_cmd(ConfigReg::Osr, data[0] | 0x40)
+    }
+
+    pub fn power_down(&mut self) -> Result<(), R::Error> {
+        let data = [0u8; 22];
+        //self.i2c_write_read_cmd(ConfigReg::Osr, &mut data)?;
+
+        self.i2c_write_cmd(ConfigReg::Osr, data[0] & !0x40)
+    }
+
+    pub fn reset(&mut self) -> Result<(), R::Error> {
+        let data = [0u8; 22];
+        /*match self.i2c_write_read_cmd(ConfigReg::Osr, &mut data) {
+            Err(e) => return Err(e),
+            _ => {}
+        }*/
+
+        self.i2c_write_cmd(ConfigReg::Osr, data[0] | 0x08)
+    }
+
+    /// Software reset followed by the wait for the device to come back,
+    /// done with `delay`.
+    pub fn reset_and_wait<D: DelayNs>(&mut self, delay: &mut D) -> Result<(), R::Error> {
+        self.reset()?;
+        delay.delay_ms(AS7331_RESET_DELAY_MS);
+        Ok(())
+    }
+
+    /// Resets the device and applies [`Config::baseline`].
+    ///
+    /// Waits for the reset to complete, writes the configuration and leaves
+    /// the device in measurement mode, ready for [`one_shot`](Self::one_shot).
+    /// The warm-up measurements are discarded before returning, see
+    /// [`set_warmup_samples`](Self::set_warmup_samples). All waits use
+    /// `delay`.
+    pub fn reset_to_defaults<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<(), As7331Error<R::Error>> {
+        self.reset_and_wait(delay)?;
+        self.i2c_write_cmd(ConfigReg::Osr, AS7331_OSR_DOS_CONFIGURATION)?;
+        self.apply(&Config::baseline())?;
+        self.i2c_write_cmd(ConfigReg::Osr, AS7331_OSR_DOS_MEASUREMENT)?;
+        self.discard_warmup(delay)
+    }
+
+    /// Reads the current results, recovering first if the device was reset
+    /// behind the driver, e.g. by a power glitch.
+    ///
+    /// OSR is read before the results: a device that should be measuring but
+    /// reports configuration state or power down has lost its settings. The
+    /// stored config is then applied again and, after the warm-up, a new
+    /// result is taken (triggered in CMD mode, awaited in CMD and CONT mode,
+    /// where SS is set again) and returned, polling with `delay`. A failure of that second read is
+    /// returned as is.
+    pub fn read_resilient<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement, As7331Error<R::Error>> {
+        let osr = self.read_osr()?;
+        let reset = self.dos == AS7331_OSR_DOS_MEASUREMENT
+            && (osr.dos != AS7331_OSR_DOS_MEASUREMENT || osr.pd);
+        if reset {
+            if self.tracing {
+                debug!("AS7331 reset detected (OSR {:?}), restoring config", osr);
+            }
+            let cfg = self.config;
+            self.i2c_write_cmd(ConfigReg::Osr, AS7331_OSR_DOS_CONFIGURATION)?;
+            self.apply(&cfg)?;
+            self.resume_measurement()?;
+            self.discard_warmup(delay)?;
+            let timeout_ms =
+                convert::integration_time_ms(cfg.time, cfg.cclk) as u32 + AS7331_WARMUP_TIMEOUT_MS;
+            match cfg.mmode {
+                AS7331_CREG3_MMODE_CMD => {
+                    self.one_shot()?;
+                    self.wait_for_data_with(timeout_ms, delay)?;
+                }
+                AS7331_CREG3_MMODE_CONT => self.wait_for_data_with(timeout_ms, delay)?,
+                _ => {}
+            }
+        }
+        let raw = self.read_all_data()?;
+        Ok(self.decode(raw))
+    }
+
+    /// Waits for and reads the warm-up measurements, triggering each in CMD
+    /// mode. Skipped in SYNS and SYND mode, which need an external trigger.
+    fn discard_warmup<D: DelayNs>(&mut self, delay: &mut D) -> Result<(), As7331Error<R::Error>> {
+        if !matches!(
+            self.config.mmode,
+            AS7331_CREG3_MMODE_CMD | AS7331_CREG3_MMODE_CONT
+        ) {
+            return Ok(());
+        }
+        let timeout_ms = convert::integration_time_ms(self.config.time, self.config.cclk) as u32
+            + AS7331_WARMUP_TIMEOUT_MS;
+        for _ in 0..self.warmup_samples {
+            if self.config.mmode == AS7331_CREG3_MMODE_CMD {
+                self.one_shot()?;
+            }
+            self.wait_for_data_with(timeout_ms, delay)?;
+            self.read_all_data()?;
+        }
+        Ok(())
+    }
+
+    /// Puts the device into its lowest-power state.
+    ///
+    /// Clears SS to stop a running measurement (only in measurement mode),
+    /// enters configuration mode and sets PD, in that order.
+    pub fn sleep(&mut self) -> Result<(), R::Error> {
+        if self.dos == AS7331_OSR_DOS_MEASUREMENT {
+            self.i2c_write_cmd(ConfigReg::Osr, AS7331_OSR_DOS_MEASUREMENT)?;
+        }
+        self.i2c_write_cmd(ConfigReg::Osr, AS7331_OSR_DOS_CONFIGURATION)?;
+        self.i2c_write_cmd(
+            ConfigReg::Osr,
+            AS7331_OSR_PD_ON << 6 | AS7331_OSR_DOS_CONFIGURATION,
+        )
+    }
+
+    /// Leaves [`sleep`](Self::sleep): clears PD, re-applies the stored config
+    /// and returns to measurement mode, restarting the conversions in CONT
+    /// mode. The warm-up measurements are discarded before returning, see
+    /// [`set_warmup_samples`](Self::set_warmup_samples), waiting with `delay`.
+    pub fn wake<D: DelayNs>(&mut self, delay: &mut D) -> Result<(), As7331Error<R::Error>> {
+        self.i2c_write_cmd(ConfigReg::Osr, AS7331_OSR_DOS_CONFIGURATION)?;
+        let cfg = self.config;
+        self.apply(&cfg)?;
+        self.resume_measurement()?;
+        self.discard_warmup(delay)
+    }
+
+    /// Enters measurement mode after the configuration was written.
+    ///
+    /// In CONT mode SS is set as well, without it no conversion runs. CMD
+    /// and SYNS mode start their conversions with [`one_shot`](Self::one_shot).
+    fn resume_measurement(&mut self) -> Result<(), R::Error> {
+        let mut osr = AS7331_OSR_DOS_MEASUREMENT;
+        if self.config.mmode == AS7331_CREG3_MMODE_CONT {
+            osr |= AS7331_OSR_SS_MEASUREMENT << 7;
+        }
+        self.i2c_write_cmd(ConfigReg::Osr, osr)
+    }
+
+    pub fn set_configuration_mode(&mut self) -> Result<(), R::Error> {
+        let data = [0u8; 22];
+        /*match self.i2c_write_read_cmd(ConfigReg::Osr, &mut data) {
+            Err(e) => return Err(e),
+            _ => {}
+        }*/
+
+        self.i2c_write_cmd(ConfigReg::Osr, data[0] | 0x02)
+    }
+
+    pub fn get_mode(&mut self) -> Result<[u8; 4], R::Error> {
+        let mut raw_data = [0u8; 2];
+        self.i2c_read_bytes(ConfigReg::Osr, &mut raw_data)?;
+        Ok([
+            (raw_data[0] & 0x07),
+            (raw_data[0] & 0x08) >> 3,
+            (raw_data[0] & 0x40) >> 6,
+            (raw_data[0] & 0x80) >> 7,
+        ])
+    }
+
+    /// Collects OSR, chip id, status and temperature for a bug report.
+    ///
+    /// Only reads registers that are available in the current state, see
+    /// [`HealthReport`]; the device state and configuration are not touched.
+    /// In measurement mode OSR, STATUS and TEMP are read in one transfer.
+    pub fn health_check(&mut self) -> Result<HealthReport, As7331Error<R::Error>> {
+        let osr = self.read_osr()?;
+        let mut report = HealthReport {
+            osr,
+            chip_id: None,
+            chip_id_valid: None,
+            status: None,
+            temperature: None,
+        };
+        if osr.dos == AS7331_OSR_DOS_MEASUREMENT {
+            let mut data = [0u8; 4];
+            self.i2c_read_bytes(ConfigReg::Osr, &mut data)?;
+            report.osr = Osr::from_byte(data[0]);
+            report.status = Some(Status::from_byte(data[1]));
+            let raw = ((data[3] as u16) << 8) | (data[2] as u16);
+            report.temperature = Some(temperature_celsius(raw));
+        } else {
+            let id = self.get_chip_id()?;
+            report.chip_id = Some(id);
+            report.chip_id_valid =
+                Some(DeviceInfo::from_agen(id).device_type == AS7331_DEVICE_TYPE);
+        }
+        Ok(report)
+    }
+
+    /// Reads OSR, which is available in both configuration and measurement
+    /// state.
+    pub fn read_osr(&mut self) -> Result<Osr, R::Error> {
+        let mut data = [0u8; 1];
+        self.i2c_write_read_cmd(ConfigReg::Osr, &mut data)?;
+        Ok(Osr::from_byte(data[0]))
+    }
+
+    pub fn set_measurement_mode(&mut self) -> Result<(), R::Error> {
+        let data = [0u8; 22];
+        /*match self.i2c_write_read_cmd(ConfigReg::Osr, &mut data) {
+            Err(e) => return Err(e),
+            _ => {}
+        }*/
+
+        self.i2c_write_cmd(ConfigReg::Osr, data[0] | 0x83)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct MockError;
+
+    /// Register model of one AS7331 for [`RegisterAccess`].
+    ///
+    /// In configuration state the registers are addressed bytewise, in
+    /// measurement state as 16-bit words with OSR and STATUS at 0x00. A STATUS
+    /// read with SS set loads the next queued result block and raises NDATA,
+    /// a read covering MRES3 clears NDATA. In CMD mode SS clears once a block
+    /// is delivered.
+    struct Mock {
+        osr: u8,
+        config: [u8; 12],
+        /// TEMP, MRES1, MRES2, MRES3, OUTCONVL, OUTCONVH
+        results: [u16; 6],
+        queue: VecDeque<[u16; 4]>,
+        /// STATUS bits reported besides NOTREADY and NDATA
+        flags: u8,
+        ndata: bool,
+        /// STATUS reads reporting NOTREADY before the next block is delivered
+        busy_polls: u32,
+        fail_reads: u32,
+        fail_writes: u32,
+        fail_reg: Option<u8>,
+        writes: Vec<(u8, u8)>,
+        reads: Vec<(u8, usize)>,
+        /// reads (`None`) and writes (`Some(val)`) by register in bus order
+        ops: Vec<(u8, Option<u8>)>,
+    }
+
+    impl Mock {
+        fn new() -> Self {
+            let mut config = [0u8; 12];
+            config[ConfigReg::Agen.addr() as usize] = 0x21;
+            config[6..].copy_from_slice(&AS7331_CONFIG_DEFAULTS);
+            Mock {
+                osr: AS7331_OSR_DEFAULT,
+                config,
+                results: [0; 6],
+                queue: VecDeque::new(),
+                flags: 0,
+                ndata: false,
+                busy_polls: 0,
+                fail_reads: 0,
+                fail_writes: 0,
+                fail_reg: None,
+                writes: Vec::new(),
+                reads: Vec::new(),
+                ops: Vec::new(),
+            }
+        }
+
+        fn measuring(&self) -> bool {
+            self.osr & 0x07 == AS7331_OSR_DOS_MEASUREMENT
+        }
+
+        fn status(&mut self) -> u8 {
+            if self.osr & 0x80 != 0 && !self.ndata {
+                if self.busy_polls > 0 {
+                    self.busy_polls -= 1;
+                    return self.flags | AS7331_STATUS_NOTREADY;
+                }
+                if let Some(block) = self.queue.pop_front() {
+                    self.results[..4].copy_from_slice(&block);
+                    self.ndata = true;
+                    if self.config[8] >> 6 == AS7331_CREG3_MMODE_CMD {
+                        self.osr &= !0x80;
+                    }
+                }
+            }
+            self.flags | if self.ndata { AS7331_STATUS_NDATA } else { 0 }
+        }
+    }
+
+    impl RegisterAccess for Mock {
+        type Error = MockError;
+
+        fn read_regs(&mut self, _addr: u8, reg: u8, data: &mut [u8]) -> Result<(), MockError> {
+            self.reads.push((reg, data.len()));
+            self.ops.push((reg, None));
+            if self.fail_reads > 0 {
+                self.fail_reads -= 1;
+                return Err(MockError);
+            }
+            if self.fail_reg == Some(reg) {
+                return Err(MockError);
+            }
+            let reg = reg as usize;
+            if !self.measuring() {
+                for (i, b) in data.iter_mut().enumerate() {
+                    *b = match reg + i {
+                        0 => self.osr,
+                        a => self.config.get(a).copied().unwrap_or(0),
+                    };
+                }
+                return Ok(());
+            }
+            let mut bytes = Vec::new();
+            for r in reg..reg + data.len().div_ceil(2) {
+                let word = match r {
+                    0 if data.len() > 1 => (self.status() as u16) << 8 | self.osr as u16,
+                    0 => self.osr as u16,
+                    r => self.results.get(r - 1).copied().unwrap_or(0),
+                };
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+            data.copy_from_slice(&bytes[..data.len()]);
+            let last = reg + (data.len() - 1) / 2;
+            if reg <= MeasReg::Mres3.addr() as usize && last >= MeasReg::Mres3.addr() as usize {
+                self.ndata = false;
+            }
+            Ok(())
+        }
+
+        fn write_reg(&mut self, _addr: u8, reg: u8, val: u8) -> Result<(), MockError> {
+            self.writes.push((reg, val));
+            self.ops.push((reg, Some(val)));
+            if self.fail_writes > 0 {
+                self.fail_writes -= 1;
+                return Err(MockError);
+            }
+            if reg == ConfigReg::Osr.addr() {
+                if val & 0x08 != 0 {
+                    let Mock {
+                        writes, reads, ops, ..
+                    } = core::mem::replace(self, Mock::new());
+                    self.writes = writes;
+                    self.reads = reads;
+                    self.ops = ops;
+                } else if val & 0x07 == AS7331_OSR_DOS_NOP {
+                    self.osr = val | self.osr & 0x07;
+                } else {
+                    self.osr = val;
+                }
+            } else if !self.measuring() {
+                self.config[reg as usize] = val;
+            }
+            Ok(())
+        }
+    }
+
+    /// Counts the delays and sums up their length.
+    #[derive(Default)]
+    struct MockDelay {
+        calls: u32,
+        ns: u64,
+    }
+
+    impl DelayNs for MockDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.calls += 1;
+            self.ns += ns as u64;
+        }
+    }
+
+    fn dev(mock: Mock) -> As7331<Mock> {
+        As7331::new(mock, 0x74)
+    }
+
+    /// Driver and mock in measurement state with SS set and `mmode`.
+    fn measuring(mmode: u8) -> As7331<Mock> {
+        let mut mock = Mock::new();
+        mock.osr = 0x80 | AS7331_OSR_DOS_MEASUREMENT;
+        mock.config[8] = mmode << 6;
+        let mut dev = dev(mock);
+        dev.config.mmode = mmode;
+        dev.dos = AS7331_OSR_DOS_MEASUREMENT;
+        dev
+    }
+
+    #[test]
+    fn chip_id_and_results_through_register_access() {
+        let mut dev = dev(Mock::new());
+        assert_eq!(dev.get_chip_id(), Ok(0x21));
+        assert_eq!(dev.i2c.reads, [(ConfigReg::Agen.addr(), 1)]);
+
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[..4].copy_from_slice(&[0x0102, 0x0304, 0x0506, 0xfffe]);
+        assert_eq!(dev.read_all_data(), Ok([0x0102, 0x0304, 0x0506, 0xfffe]));
+        dev.i2c.fail_reads = 1;
+        assert_eq!(dev.read_all_data(), Err(MockError));
+    }
+
+    /// `embedded_hal` bus with AS7331s answering at `present`.
+    #[cfg(feature = "heapless")]
+    struct ScanBus {
+        present: [u8; 2],
+    }
+
+    #[cfg(feature = "heapless")]
+    impl embedded_hal::i2c::ErrorType for ScanBus {
+        type Error = embedded_hal::i2c::ErrorKind;
+    }
+
+    #[cfg(feature = "heapless")]
+    impl embedded_hal::i2c::I2c for ScanBus {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [embedded_hal::i2c::Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource, Operation};
+            if !self.present.contains(&address) {
+                return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+            }
+            for op in operations {
+                if let Operation::Read(buf) = op {
+                    buf.fill(0x21);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn scan_keeps_responding_addresses() {
+        let mut bus = ScanBus {
+            present: [0x75, 0x77],
+        };
+        assert_eq!(scan(&mut bus).as_slice(), [0x75, 0x77]);
+    }
+
+    #[test]
+    fn delay_runs_between_status_polls() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.busy_polls = 3;
+        dev.i2c.queue.push_back([0; 4]);
+        let mut delay = MockDelay::default();
+        dev.wait_for_data_with(10, &mut delay).unwrap();
+        let polls = dev.i2c.reads.iter().filter(|r| **r == (0, 2)).count();
+        assert_eq!(polls, 4);
+        assert_eq!(delay.calls, 3);
+        assert_eq!(delay.ns, 3_000_000);
+
+        dev.read_all_data().unwrap();
+        dev.i2c.busy_polls = u32::MAX;
+        let mut delay = MockDelay::default();
+        assert!(matches!(
+            dev.wait_for_data_with(5, &mut delay),
+            Err(As7331Error::Timeout)
+        ));
+        assert_eq!(delay.calls, 5);
+    }
+
+    #[test]
+    fn wake_restarts_continuous_conversions() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.sleep().unwrap();
+        assert_eq!(dev.i2c.osr, 0x42);
+        dev.i2c.queue.push_back([0; 4]);
+        let mut delay = MockDelay::default();
+        dev.wake(&mut delay).unwrap();
+        assert_eq!(dev.i2c.osr, 0x83);
+        assert!(dev.i2c.queue.is_empty());
+    }
+
+    #[test]
+    fn wake_discards_warmup_samples() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.set_warmup_samples(2);
+        dev.sleep().unwrap();
+        dev.i2c.queue.extend([[1; 4], [2; 4], [3; 4]]);
+        dev.wake(&mut MockDelay::default()).unwrap();
+        assert_eq!(dev.i2c.queue, [[3; 4]]);
+        let triggers = dev.i2c.writes.iter().filter(|w| **w == (0, 0x83)).count();
+        assert_eq!(triggers, 2);
+    }
+
+    #[test]
+    fn read_resilient_restarts_continuous_mode_after_reset() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.set_warmup_samples(1);
+        let writes = core::mem::take(&mut dev.i2c.writes);
+        dev.i2c = Mock {
+            writes,
+            ..Mock::new()
+        };
+        dev.i2c.queue.extend([[1; 4], [0x10, 100, 200, 300]]);
+        let m = dev.read_resilient(&mut MockDelay::default()).unwrap();
+        assert_eq!(m.raw, [0x10, 100, 200, 300]);
+        assert_eq!(dev.i2c.osr, 0x83);
+        assert_eq!(dev.i2c.config[8] >> 6, AS7331_CREG3_MMODE_CONT);
+    }
+
+    #[test]
+    fn creg_setters_pass_through_configuration_mode() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.set_gain(AS7331_CREG1_GAIN_32).unwrap();
+        let creg1 = pack_creg1(AS7331_CREG1_GAIN_32, dev.config.time);
+        assert_eq!(dev.i2c.writes, [(0, 0x02), (6, creg1), (0, 0x83)]);
+        assert_eq!(dev.i2c.osr, 0x83);
+
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.write_field(ConfigField::StartState, |dev| {
+            dev.i2c_write_cmd(ConfigReg::Osr, 0x83)
+        })
+        .unwrap();
+        assert_eq!(dev.i2c.writes, [(0, 0x83)]);
+        assert!(dev.i2c.reads.is_empty());
+    }
+
+    #[test]
+    fn headroom_uses_full_scale_of_integration_time() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.config.time = AS7331_CREG1_TIME_1;
+        dev.i2c.results[1..4].copy_from_slice(&[1024, 512, 0]);
+        assert_eq!(dev.headroom().unwrap(), [1.0, 0.5, 0.0]);
+        let lsb = dev.resolution();
+        assert_eq!(dev.full_scale_range()[0], lsb[0] * 1024.0);
+    }
+
+    #[test]
+    fn autorange_window_follows_integration_time() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.config.time = AS7331_CREG1_TIME_1;
+        let gain = dev.config.gain;
+        dev.i2c
+            .queue
+            .extend([[0, 1000, 0, 0], [0; 4], [0, 600, 0, 0]]);
+        dev.uv_index_autorange(10, &mut MockDelay::default())
+            .unwrap();
+        assert_eq!(dev.config.gain, gain + 1);
+        assert!(dev.i2c.queue.is_empty());
+    }
+
+    #[test]
+    fn autorange_treats_overflow_as_saturated() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.config.gain = AS7331_CREG1_GAIN_1;
+        dev.i2c.flags = AS7331_STATUS_ADCOF;
+        dev.i2c.queue.push_back([0, 500, 0, 0]);
+        let uvi = dev.uv_index_autorange(10, &mut MockDelay::default());
+        assert!(uvi.is_ok());
+        assert_eq!(dev.config.gain, AS7331_CREG1_GAIN_1);
+        assert!(!dev.i2c.writes.iter().any(|w| w.0 == 6));
+    }
+
+    #[test]
+    fn range_hint_scales_with_integration_time() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[1..4].copy_from_slice(&[1000, 0, 0]);
+        assert_eq!(
+            dev.read_with_range_hint().unwrap().1,
+            RangeHint::IncreaseGain
+        );
+        dev.config.time = AS7331_CREG1_TIME_1;
+        assert_eq!(
+            dev.read_with_range_hint().unwrap().1,
+            RangeHint::DecreaseGain
+        );
+        dev.i2c.results[1] = 600;
+        assert_eq!(dev.read_with_range_hint().unwrap().1, RangeHint::Ok);
+        dev.i2c.flags = AS7331_STATUS_MRESOF;
+        assert_eq!(
+            dev.read_with_range_hint().unwrap().1,
+            RangeHint::DecreaseGain
+        );
+    }
+
+    #[test]
+    fn would_saturate_uses_full_scale_of_integration_time() {
+        let lsb = convert::lsb(AS7331_CREG1_GAIN_2, AS7331_CREG1_TIME_1, 0)[0];
+        let at = |counts: f32, time| would_saturate(counts * lsb, Gain::X2, time, Channel::Uva);
+        assert!(!at(1000.0, IntegrationTime::Ms1));
+        assert!(at(1100.0, IntegrationTime::Ms1));
+        let lsb64 = convert::lsb(AS7331_CREG1_GAIN_2, AS7331_CREG1_TIME_64, 0)[0];
+        assert!(!would_saturate(
+            60000.0 * lsb64,
+            Gain::X2,
+            IntegrationTime::Ms64,
+            Channel::Uva
+        ));
+        assert!(would_saturate(
+            70000.0 * lsb64,
+            Gain::X2,
+            IntegrationTime::Ms64,
+            Channel::Uva
+        ));
+    }
+
+    #[test]
+    fn max_measurable_irradiance_uses_full_scale_of_integration_time() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.config.time = AS7331_CREG1_TIME_2;
+        dev.set_calibration(Calibration {
+            scale: [2.0, 1.0, 1.0],
+            ..Calibration::default()
+        });
+        let lsb = dev.resolution();
+        let max = dev.max_measurable_irradiance();
+        assert_eq!(max[0], lsb[0] * 2048.0 * 2.0);
+        assert_eq!(max[1], lsb[1] * 2048.0);
+    }
+
+    #[test]
+    fn sample_rate_is_timed_with_the_clock() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.queue.extend([[0; 4], [0; 4], [0; 4]]);
+        let mut delay = MockDelay::default();
+        let mut t = 0;
+        let clock = || {
+            t += 2000;
+            t
+        };
+        let rate = dev.measured_sample_rate(10, &mut delay, clock).unwrap();
+        assert_eq!(rate, 300.0);
+        assert_eq!(delay.calls, 4);
+    }
+
+    #[test]
+    fn temperature_stable_waits_for_new_results_in_cont_mode() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c
+            .queue
+            .extend([[1000; 4], [1200; 4], [1201; 4], [1202; 4]]);
+        let stable = dev.temperature_stable(3, 100, 10, &mut MockDelay::default());
+        assert!(stable.unwrap());
+        assert!(dev.i2c.queue.is_empty());
+        assert!(!dev
+            .temperature_stable(3, 100, 10, &mut MockDelay::default())
+            .unwrap());
+    }
+
+    #[cfg(feature = "async")]
+    struct AsyncDelay {
+        calls: u32,
+    }
+
+    #[cfg(feature = "async")]
+    impl embedded_hal_async::delay::DelayNs for AsyncDelay {
+        async fn delay_ns(&mut self, _ns: u32) {
+            self.calls += 1;
+            let mut yielded = false;
+            core::future::poll_fn(|cx| {
+                if yielded {
+                    core::task::Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    core::task::Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn stream_awaits_the_delay_between_polls() {
+        use core::task::{Context, Poll};
+        use futures_core::Stream;
+
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.busy_polls = 2;
+        dev.i2c.queue.push_back([0x10, 1, 2, 3]);
+        let mut delay = AsyncDelay { calls: 0 };
+        let waker = futures_util::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        {
+            let mut stream = core::pin::pin!(dev.measurement_stream(&mut delay));
+            assert!(stream.as_mut().poll_next(&mut cx).is_pending());
+            assert!(stream.as_mut().poll_next(&mut cx).is_pending());
+            match stream.as_mut().poll_next(&mut cx) {
+                Poll::Ready(Some(Ok(m))) => assert_eq!(m.raw, [0x10, 1, 2, 3]),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(delay.calls, 2);
+        assert_eq!(dev.completed_conversions(), 1);
+    }
+
+    struct CaptureLogger;
+
+    std::thread_local! {
+        static LOG: core::cell::RefCell<Vec<std::string::String>> =
+            const { core::cell::RefCell::new(Vec::new()) };
+    }
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOG.with(|l| l.borrow_mut().push(std::format!("{}", record.args())));
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Runs `f` and returns what it logged on this thread.
+    fn capture_log<T>(f: impl FnOnce() -> T) -> (T, Vec<std::string::String>) {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Debug);
+        });
+        LOG.with(|l| l.borrow_mut().clear());
+        let out = f();
+        (out, LOG.with(|l| l.take()))
+    }
+
+    #[test]
+    fn tracing_off_silences_the_temperature_read_failure() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.fail_reg = Some(MeasReg::Temp.addr());
+        dev.i2c.queue.extend([[0; 4], [0; 4]]);
+        let (m, log) = capture_log(|| dev.measure_uv(10, &mut MockDelay::default()));
+        assert_eq!(m.unwrap().temperature, None);
+        assert!(log.iter().any(|l| l.contains("temperature read failed")));
+
+        dev.set_tracing(false);
+        let (m, log) = capture_log(|| dev.measure_uv(10, &mut MockDelay::default()));
+        assert_eq!(m.unwrap().temperature, None);
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn dark_reading_outcomes() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        assert_eq!(dev.is_dark_reading().unwrap(), DarkReading::NotMeasuring);
+        dev.i2c.queue.push_back([0x10, 0, 0, 0]);
+        dev.wait_for_data_with(10, &mut MockDelay::default())
+            .unwrap();
+        assert_eq!(dev.is_dark_reading().unwrap(), DarkReading::Dark);
+        dev.i2c.results[3] = 1;
+        assert_eq!(dev.is_dark_reading().unwrap(), DarkReading::Lit);
+    }
+
+    #[test]
+    fn window_transmission_scales_and_is_validated() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        let lsb = dev.resolution();
+        dev.set_window_transmission(0.5, 1.0, 0.25).unwrap();
+        assert_eq!(dev.resolution(), [lsb[0] * 2.0, lsb[1], lsb[2] * 4.0]);
+        for bad in [0.0, -0.5, 1.5, f32::NAN] {
+            assert!(matches!(
+                dev.set_window_transmission(1.0, bad, 1.0),
+                Err(As7331Error::InvalidConfig {
+                    field: "window_transmission",
+                    ..
+                })
+            ));
+        }
+        assert_eq!(dev.resolution(), [lsb[0] * 2.0, lsb[1], lsb[2] * 4.0]);
+    }
+
+    #[test]
+    fn autorange_raises_gain_until_in_window() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        let gain = dev.config.gain;
+        dev.i2c
+            .queue
+            .extend([[0, 0x1000, 0, 0], [0; 4], [0, 0x8000, 0, 0]]);
+        dev.uv_index_autorange(10, &mut MockDelay::default())
+            .unwrap();
+        assert_eq!(dev.config.gain, gain - 1);
+        assert_eq!(dev.i2c.config[6] >> 4, gain - 1);
+        assert!(dev.i2c.queue.is_empty());
+    }
+
+    #[test]
+    fn status_word_combines_status_and_osr() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.flags = AS7331_STATUS_ADCOF;
+        assert_eq!(dev.get_status_raw().unwrap(), 0x2083);
+        assert!(dev.adc_overflowed().unwrap());
+        assert!(!dev.mres_overflowed().unwrap());
+    }
+
+    #[test]
+    fn creg1_round_trips_every_gain_and_time() {
+        let mut dev = dev(Mock::new());
+        for gain in AS7331_CREG1_GAIN_2048..=AS7331_CREG1_GAIN_1 {
+            for time in AS7331_CREG1_TIME_1..=AS7331_CREG1_TIME_16384 {
+                assert_eq!(unpack_creg1(pack_creg1(gain, time)), (gain, time));
+                let cfg = Config {
+                    gain,
+                    time,
+                    ..Config::default()
+                };
+                dev.apply(&cfg).unwrap();
+                assert_eq!(dev.read_config().unwrap(), cfg);
+            }
+        }
+    }
+
+    #[test]
+    fn current_estimate_follows_duty_cycle() {
+        let cfg = Config::default();
+        assert_eq!(estimate_current_ua(&cfg, 0.0), AS7331_CURRENT_POWER_DOWN_UA);
+        let ua = estimate_current_ua(&cfg, 5.0);
+        assert!((ua - (0.32 * 1500.0 + 0.68 * 970.0)).abs() < 0.01);
+        assert_eq!(estimate_current_ua(&cfg, 100.0), AS7331_CURRENT_ACTIVE_UA);
+        let no_standby = Config {
+            sb: AS7331_CREG3_SB_OFF,
+            ..cfg
+        };
+        assert_eq!(
+            estimate_current_ua(&no_standby, 5.0),
+            AS7331_CURRENT_ACTIVE_UA
+        );
+    }
+
+    #[test]
+    fn measure_uv_keeps_uv_when_temperature_fails() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.queue.push_back([0x500, 100, 200, 300]);
+        dev.i2c.fail_reg = Some(MeasReg::Temp.addr());
+        let m = dev.measure_uv(10, &mut MockDelay::default()).unwrap();
+        assert_eq!(m.raw, [100, 200, 300]);
+        assert_eq!(m.temperature, None);
+        assert_eq!(m.uva, 100.0 * dev.resolution()[0]);
+
+        dev.i2c.fail_reg = None;
+        dev.i2c.queue.push_back([0x500, 100, 200, 300]);
+        let m = dev.measure_uv(10, &mut MockDelay::default()).unwrap();
+        assert_eq!(m.temperature, Some(temperature_celsius(0x500)));
+    }
+
+    #[test]
+    fn ready_flag_depends_on_measurement_mode() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.busy_polls = 1;
+        assert!(dev.conversion_in_progress().unwrap());
+        assert!(!dev.conversion_in_progress().unwrap());
+        dev.wait_for_data_with(0, &mut MockDelay::default())
+            .unwrap();
+
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        let waited = dev.wait_for_data_with(2, &mut MockDelay::default());
+        assert!(matches!(waited, Err(As7331Error::Timeout)));
+        dev.i2c.queue.push_back([0; 4]);
+        dev.wait_for_data_with(0, &mut MockDelay::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn fixed_point_reading_matches_float() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[..4].copy_from_slice(&[1338, 1000, 2000, 3000]);
+        let fixed = dev.read_scaled_fixed().unwrap();
+        let m = dev.decode([1338, 1000, 2000, 3000]);
+        assert_eq!(fixed.temperature_mc, 0);
+        let nw = [fixed.uva_nw, fixed.uvb_nw, fixed.uvc_nw];
+        for (nw, uw) in nw.iter().zip([m.uva, m.uvb, m.uvc]) {
+            assert!((*nw as f32 - uw * 1000.0).abs() <= 1.0, "{} vs {}", nw, uw);
+        }
+    }
+
+    #[test]
+    fn mode_switch_keeps_other_creg3_bits() {
+        let mut dev = dev(Mock::new());
+        dev.i2c.config[8] = 0x5B;
+        dev.set_measurement_mode_mmode(MeasurementMode::Continuous)
+            .unwrap();
+        assert_eq!(dev.i2c.config[8], 0x1B);
+        assert_eq!(dev.config.mmode, AS7331_CREG3_MMODE_CONT);
+        dev.set_measurement_mode_mmode(MeasurementMode::Command)
+            .unwrap();
+        assert_eq!(dev.i2c.config[8], 0x5B);
+        for mode in [
+            MeasurementMode::Continuous,
+            MeasurementMode::Command,
+            MeasurementMode::SynStart,
+            MeasurementMode::SynStartEnd,
+        ] {
+            assert_eq!(MeasurementMode::from_bits(mode.bits()), mode);
+        }
+    }
+
+    #[test]
+    fn smoothed_polling_applies_ema_steps() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c
+            .queue
+            .extend([[0, 0, 0, 0], [0, 1000, 0, 0], [0, 1000, 0, 0]]);
+        let lsb = dev.resolution()[0];
+        let mut smoother = EmaSmoother::new(0.5);
+        let mut seen = Vec::new();
+        dev.poll_continuous_smoothed(10, &mut MockDelay::default(), &mut smoother, |m, s| {
+            seen.push((m.uva, s[0]));
+            seen.len() < 3
+        })
+        .unwrap();
+        let expected = [(0.0, 0.0), (1000.0, 500.0), (1000.0, 750.0)];
+        for ((raw, smooth), (er, es)) in seen.iter().zip(expected) {
+            assert!((raw - er * lsb).abs() < 1e-3);
+            assert!((smooth - es * lsb).abs() < 1e-3);
+        }
+        smoother.reset();
+        assert_eq!(smoother.value(), None);
+        assert_eq!(EmaSmoother::new(3.0).alpha, 1.0);
+    }
+
+    #[test]
+    fn standby_switch_keeps_continuous_mode_running() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.flags = AS7331_STATUS_STANDBYSTATE;
+        assert!(dev.enter_standby().unwrap());
+        assert_eq!(
+            dev.i2c.config[8] & AS7331_CREG3_SB_MASK,
+            AS7331_CREG3_SB_MASK
+        );
+        assert_eq!(dev.i2c.osr, 0x83);
+        assert!(!dev.exit_standby().unwrap());
+        dev.i2c.flags = 0;
+        assert!(dev.exit_standby().unwrap());
+        assert_eq!(dev.i2c.config[8] & AS7331_CREG3_SB_MASK, 0);
+        assert_eq!(dev.config.sb, AS7331_CREG3_SB_OFF);
+    }
+
+    #[test]
+    fn conversion_count_includes_overwritten_results() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.queue.extend([[0; 4], [0; 4], [0; 4]]);
+        let mut delay = MockDelay::default();
+        dev.poll_continuous(10, &mut delay, |_| false).unwrap();
+        dev.i2c.flags = AS7331_STATUS_LDATA;
+        let mut n = 0;
+        dev.poll_continuous(10, &mut delay, |_| {
+            n += 1;
+            n < 2
+        })
+        .unwrap();
+        assert_eq!(dev.missed_conversions(), 2);
+        assert_eq!(dev.completed_conversions(), 5);
+        dev.reset_conversion_count();
+        assert_eq!(dev.completed_conversions(), 0);
+    }
+
+    #[test]
+    fn break_time_zero_rejected_in_back_to_back_modes() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        assert!(matches!(
+            dev.set_break_time(0),
+            Err(As7331Error::InvalidConfig {
+                field: "break_time",
+                value: 0
+            })
+        ));
+        assert!(dev.i2c.writes.is_empty());
+        dev.set_break_time(0x20).unwrap();
+        assert_eq!(dev.i2c.config[9], 0x20);
+
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.set_break_time(0).unwrap();
+        assert_eq!(dev.config.break_time, 0);
+    }
+
+    #[test]
+    fn apply_if_changed_skips_unchanged_registers() {
+        let mut dev = dev(Mock::new());
+        let cfg = Config::default();
+        assert!(!dev.apply_if_changed(&cfg).unwrap());
+        assert!(dev.i2c.writes.is_empty());
+
+        let cfg = Config {
+            gain: AS7331_CREG1_GAIN_16,
+            break_time: 0x30,
+            ..cfg
+        };
+        assert!(dev.apply_if_changed(&cfg).unwrap());
+        let regs: Vec<u8> = dev.i2c.writes.iter().map(|w| w.0).collect();
+        assert_eq!(regs, [6, 9]);
+        assert_eq!(dev.read_config().unwrap(), cfg);
+        assert!(!dev.apply_if_changed(&cfg).unwrap());
+    }
+
+    #[test]
+    fn uvc_fast_reads_mres3_only() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[3] = 0x1234;
+        assert_eq!(dev.read_uvc_fast().unwrap(), 0x1234);
+        assert_eq!(dev.i2c.reads, [(MeasReg::Mres3.addr(), 2)]);
+    }
+
+    #[test]
+    fn overflow_reason_follows_status_bits() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        assert_eq!(dev.overflow_reason().unwrap(), None);
+        dev.i2c.flags = AS7331_STATUS_OUTCONVOF;
+        assert!(dev.conversion_overflowed().unwrap());
+        assert_eq!(
+            dev.overflow_reason().unwrap(),
+            Some(OverflowReason::Conversion)
+        );
+        dev.i2c.flags |= AS7331_STATUS_MRESOF;
+        assert_eq!(dev.overflow_reason().unwrap(), Some(OverflowReason::Mres));
+        dev.i2c.flags |= AS7331_STATUS_ADCOF;
+        assert_eq!(dev.overflow_reason().unwrap(), Some(OverflowReason::Adc));
+    }
+
+    #[test]
+    fn agen_splits_device_type_and_mutation() {
+        let info = DeviceInfo::from_agen(0x21);
+        assert_eq!((info.device_type, info.mutation), (0x2, 0x1));
+        let mut dev = dev(Mock::new());
+        dev.i2c.config[2] = 0x2A;
+        assert_eq!(dev.device_type().unwrap(), AS7331_DEVICE_TYPE);
+        assert_eq!(dev.mutation().unwrap(), 0xA);
+        assert!(dev.i2c.writes.is_empty());
+    }
+
+    #[test]
+    fn power_on_default_detection() {
+        let mut dev = dev(Mock::new());
+        assert!(dev.is_in_power_on_default().unwrap());
+        dev.set_gain(AS7331_CREG1_GAIN_1).unwrap();
+        assert!(!dev.is_in_power_on_default().unwrap());
+        dev.reset().unwrap();
+        assert!(dev.is_in_power_on_default().unwrap());
+        dev.set_configuration_mode().unwrap();
+        assert!(!dev.is_in_power_on_default().unwrap());
+    }
+
+    #[test]
+    fn syns_measurement_waits_for_the_syn_edge() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        let mut delay = MockDelay::default();
+        let waited = dev.measure_syns(3, &mut delay);
+        assert!(matches!(waited, Err(As7331Error::Timeout)));
+        assert_eq!(dev.i2c.config[8] >> 6, AS7331_CREG3_MMODE_SYNS);
+        assert_eq!(delay.calls, 3);
+
+        dev.i2c.queue.push_back([0x10, 1, 2, 3]);
+        let m = dev.measure_syns(3, &mut delay).unwrap();
+        assert_eq!(m.raw, [0x10, 1, 2, 3]);
+    }
+
+    #[test]
+    fn per_channel_autorange_reads_each_channel_at_its_gain() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        let gain = dev.config.gain;
+        dev.i2c.queue.extend([
+            [0, 0xF000, 0, 0],
+            [0; 4],
+            [0, 0x9000, 0x1000, 0],
+            [0, 0, 0x1000, 0],
+            [0; 4],
+            [0, 0, 0x8000, 0],
+            [0, 0, 0, 0x8000],
+        ]);
+        let uv = dev
+            .measure_per_channel_autorange(10, &mut MockDelay::default())
+            .unwrap();
+        assert!(dev.i2c.queue.is_empty());
+        assert_eq!(dev.config.gain, gain);
+        let lsb = |gain| convert::resolution(&Config { gain, ..dev.config });
+        assert_eq!(uv[0], 0x9000 as f32 * lsb(gain + 1)[0]);
+        assert_eq!(uv[1], 0x8000 as f32 * lsb(gain)[1]);
+        assert_eq!(uv[2], 0x8000 as f32 * lsb(gain)[2]);
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn csv_rows_into_a_buffer() {
+        let dev = dev(Mock::new());
+        let m = Measurement {
+            raw: [0; 4],
+            temperature: 25.0,
+            uva: 1.5,
+            uvb: 2.0,
+            uvc: 0.125,
+        };
+        let mut buf = [0u8; 64];
+        let mut w = &mut buf[..];
+        dev.write_csv_header(&mut w).unwrap();
+        dev.write_csv_row(&m, &mut w).unwrap();
+        let len = 64 - w.len();
+        assert_eq!(
+            core::str::from_utf8(&buf[..len]).unwrap(),
+            "temp_c,uva,uvb,uvc\n25.00,1.500,2.000,0.125\n"
+        );
+        let mut small = [0u8; 8];
+        assert!(dev.write_csv_row(&m, &mut &mut small[..]).is_err());
+    }
+
+    #[test]
+    fn divider_decodes_and_reads_back() {
+        assert_eq!(Divider::from_creg2(0x00), Divider::Off);
+        assert_eq!(Divider::from_creg2(0x07), Divider::Off);
+        assert_eq!(Divider::from_creg2(0x08), Divider::Div2);
+        assert_eq!(Divider::from_creg2(0x4F), Divider::Div256);
+        for shift in 0..8 {
+            let div = Divider::from_creg2(AS7331_CREG2_EN_DIV | shift);
+            assert_eq!(Divider::from_creg2(div.bits()), div);
+            assert_eq!(div.factor(), 2 << shift);
+        }
+        let mut dev = dev(Mock::new());
+        assert_eq!(dev.get_divider().unwrap(), Divider::Off);
+        dev.set_divider(Divider::Div16).unwrap();
+        assert_eq!(dev.get_divider().unwrap(), Divider::Div16);
+        assert_eq!(dev.i2c.config[7], 0x40 | Divider::Div16.bits());
+    }
+
+    #[test]
+    fn array_measurement_reads_every_device() {
+        let mut devices = [
+            measuring(AS7331_CREG3_MMODE_CMD),
+            measuring(AS7331_CREG3_MMODE_CMD),
+        ];
+        devices[0].i2c.busy_polls = 2;
+        devices[0].i2c.queue.push_back([0x10, 1, 2, 3]);
+        devices[1].i2c.queue.push_back([0x20, 4, 5, 6]);
+        let mut delay = MockDelay::default();
+        let m = measure_array(&mut devices, 10, &mut delay).unwrap();
+        assert_eq!(m[0].raw, [0x10, 1, 2, 3]);
+        assert_eq!(m[1].raw, [0x20, 4, 5, 6]);
+        assert_eq!(delay.calls, 2);
+        for dev in &devices {
+            assert_eq!(dev.i2c.writes, [(0, 0x83)]);
+        }
+    }
+
+    #[test]
+    fn raw_record_converts_like_the_driver() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.config.divider = Divider::Div4;
+        dev.i2c.results[..4].copy_from_slice(&[1500, 100, 200, 300]);
+        let rec = dev.read_raw_record().unwrap();
+        assert_eq!(rec.raw, [1500, 100, 200, 300]);
+        let cfg = Config {
+            gain: rec.gain,
+            time: rec.time,
+            divider: Divider::from_creg2(rec.divider),
+            cclk: rec.cclk,
+            ..Config::default()
+        };
+        let m = dev.decode(rec.raw);
+        assert_eq!(
+            convert::normalized_irradiance([100, 200, 300], &cfg),
+            [m.uva, m.uvb, m.uvc]
+        );
+    }
+
+    #[test]
+    fn try_new_probes_the_chip_id() {
+        assert!(As7331::try_new(Mock::new(), 0x74).is_ok());
+
+        let mut mock = Mock::new();
+        mock.config[2] = 0x31;
+        let Err((mock, e)) = As7331::try_new(mock, 0x74) else {
+            panic!("wrong chip id accepted");
+        };
+        assert!(matches!(e, As7331Error::WrongChipId(0x31)));
+        assert_eq!(mock.reads.len(), 1);
+
+        let mut mock = Mock::new();
+        mock.fail_reg = Some(ConfigReg::Agen.addr());
+        let Err((_, e)) = As7331::try_new(mock, 0x74) else {
+            panic!("bus error ignored");
+        };
+        assert!(matches!(e, As7331Error::I2c(MockError)));
+    }
+
+    #[test]
+    fn syn_edges_validated_and_read_back() {
+        let mut dev = dev(Mock::new());
+        assert!(matches!(
+            dev.set_syn_edges(0),
+            Err(As7331Error::InvalidConfig { field: "edges", .. })
+        ));
+        dev.set_syn_edges(5).unwrap();
+        assert_eq!(dev.get_syn_edges().unwrap(), 5);
+        assert_eq!(dev.config.edges, 5);
+    }
+
+    #[test]
+    fn photon_flux_matches_planck_relation() {
+        let h = 6.626_070e-34_f64;
+        let c = 2.997_925e8_f64;
+        let expected = 1e-6 / (h * c / 360e-9);
+        let flux = photon_flux(1.0, 360.0) as f64;
+        assert!((flux / expected - 1.0).abs() < 1e-4);
+
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[1..4].copy_from_slice(&[1000, 1000, 1000]);
+        dev.set_effective_wavelengths(400.0, 300.0, 250.0);
+        let m = dev.decode(dev.i2c.results[..4].try_into().unwrap());
+        let flux = dev.read_photon_flux().unwrap();
+        assert_eq!(flux[0], photon_flux(m.uva, 400.0));
+        assert_eq!(flux[2], photon_flux(m.uvc, 250.0));
+    }
+
+    #[test]
+    fn verified_write_detects_ignored_writes() {
+        let mut dev = dev(Mock::new());
+        dev.write_register_verified(ConfigReg::Creg1, 0x55).unwrap();
+        let cfg = Config {
+            gain: AS7331_CREG1_GAIN_64,
+            divider: Divider::Div8,
+            ..Config::default()
+        };
+        dev.apply_verified(&cfg).unwrap();
+        assert_eq!(dev.read_config().unwrap(), cfg);
+
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        assert!(matches!(
+            dev.write_register_verified(ConfigReg::Creg1, 0x55),
+            Err(As7331Error::WriteVerifyFailed {
+                reg: 6,
+                wrote: 0x55,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn sleep_and_wake_write_order() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.set_warmup_samples(0);
+        dev.sleep().unwrap();
+        assert_eq!(dev.i2c.writes, [(0, 0x03), (0, 0x02), (0, 0x42)]);
+        dev.i2c.writes.clear();
+        dev.wake(&mut MockDelay::default()).unwrap();
+        let writes = &dev.i2c.writes;
+        assert_eq!(writes.first(), Some(&(0, 0x02)));
+        assert_eq!(writes.last(), Some(&(0, 0x03)));
+        let creg3 = dev.config.mmode << 6 | dev.config.sb << 4 | dev.config.cclk;
+        assert_eq!(dev.i2c.config[8], creg3);
+        assert_eq!(dev.i2c.osr, 0x03);
+
+        let mut idle = As7331::new(Mock::new(), 0x74);
+        idle.sleep().unwrap();
+        assert_eq!(idle.i2c.writes, [(0, 0x02), (0, 0x42)]);
+    }
+
+    #[test]
+    fn resolution_matches_datasheet() {
+        let mut dev = dev(Mock::new());
+        dev.config.gain = AS7331_CREG1_GAIN_1;
+        dev.config.time = AS7331_CREG1_TIME_1024;
+        let close = |a: f32, b: f32| (a / b - 1.0).abs() < 1e-5;
+        let r = dev.resolution();
+        assert!(close(r[0], 0.304_69) && close(r[1], 0.398_44) && close(r[2], 0.191_41));
+        dev.config.gain = AS7331_CREG1_GAIN_2048;
+        dev.config.time = AS7331_CREG1_TIME_64;
+        assert!(close(dev.resolution()[0], 0.304_69 / 2048.0 * 16.0));
+        dev.config.divider = Divider::Div4;
+        assert!(close(dev.resolution()[0], 0.304_69 / 2048.0 * 64.0));
+    }
+
+    #[test]
+    fn compensation_is_neutral_at_reference_temperature() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.set_calibration(Calibration {
+            temp_coefficient: [0.01, 0.0, 0.0],
+            ..Calibration::default()
+        });
+        let raw = [2038, 1000, 1000, 1000];
+        let plain = 1000.0 * dev.resolution()[0];
+        let m = dev.decode(raw);
+        assert!((m.temperature - 35.0).abs() < 1e-3);
+        assert!((m.uva - plain / 1.1).abs() < 1e-3 * plain);
+        dev.set_reference_temperature(35.0);
+        assert!((dev.decode(raw).uva - plain).abs() < 1e-3 * plain);
+    }
+
+    #[test]
+    fn register_access_follows_device_state() {
+        let mut dev = dev(Mock::new());
+        assert!(matches!(
+            dev.read_meas_reg(MeasReg::Mres1),
+            Err(As7331Error::WrongMode)
+        ));
+        assert_eq!(dev.read_config_reg(ConfigReg::Agen).unwrap(), 0x21);
+        dev.write_config_reg(ConfigReg::Osr, 0x03).unwrap();
+        assert!(matches!(
+            dev.read_config_reg(ConfigReg::Creg1),
+            Err(As7331Error::WrongMode)
+        ));
+        assert!(matches!(
+            dev.write_config_reg(ConfigReg::Break, 0),
+            Err(As7331Error::WrongMode)
+        ));
+        dev.i2c.results[1] = 0xBEEF;
+        assert_eq!(dev.read_meas_reg(MeasReg::Mres1).unwrap(), 0xBEEF);
+        dev.write_config_reg(ConfigReg::Osr, 0x0A).unwrap();
+        assert!(dev.read_config_reg(ConfigReg::Creg1).is_ok());
+        assert!(dev.i2c.reads.iter().all(|r| r.0 != MeasReg::Mres2.addr()));
+    }
+
+    #[test]
+    fn si_reading_in_w_per_m2() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[..4].copy_from_slice(&[1838, 1000, 2000, 3000]);
+        let m = dev.decode([1838, 1000, 2000, 3000]);
+        let si = dev.read_si().unwrap();
+        assert!((si.temperature_c - 25.0).abs() < 1e-3);
+        assert!((si.uva_w_m2 - m.uva / 100.0).abs() < 1e-6);
+        assert!((si.uvc_w_m2 - m.uvc / 100.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn calibration_blob_round_trips() {
+        let cal = Calibration {
+            scale: [1.1, 0.9, 1.25],
+            temp_coefficient: [0.001, -0.002, 0.0],
+            reference_temperature: 22.5,
+        };
+        let blob = cal.to_bytes();
+        assert_eq!(blob[0], CALIBRATION_BLOB_VERSION);
+        assert_eq!(&blob[1..5], &1.1f32.to_le_bytes());
+        assert_eq!(Calibration::from_bytes(&blob), Ok(cal));
+        assert_eq!(
+            Calibration::from_bytes(&blob[..28]),
+            Err(CalibrationError::WrongLength(28))
+        );
+        let mut old = blob;
+        old[0] = 0;
+        assert_eq!(
+            Calibration::from_bytes(&old),
+            Err(CalibrationError::UnsupportedVersion(0))
+        );
+    }
+
+    #[test]
+    fn decimation_keeps_every_nth_result() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.queue.extend((1..=6).map(|i| [i; 4]));
+        let mut delay = MockDelay::default();
+        let kept: Vec<u16> = dev
+            .measurements_decimated(10, 3, &mut delay)
+            .take(2)
+            .map(|m| m.unwrap().raw[0])
+            .collect();
+        assert_eq!(kept, [3, 6]);
+        assert_eq!(dev.completed_conversions(), 6);
+        let mut it = dev.measurements_decimated(0, 0, &mut delay);
+        assert!(matches!(it.next(), Some(Err(As7331Error::Timeout))));
+    }
+
+    #[test]
+    fn read_and_clear_status_releases_ndata() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.queue.push_back([0; 4]);
+        assert!(dev.read_status().unwrap().ndata);
+        assert!(dev.read_status().unwrap().ndata);
+        let status = dev.read_and_clear_status().unwrap();
+        assert!(status.ndata);
+        assert!(!status.ldata);
+        assert!(!dev.read_status().unwrap().ndata);
+        assert_eq!(dev.i2c.reads.last(), Some(&(MeasReg::Status.addr(), 2)));
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected_before_writing() {
+        let field = |cfg: Config| match cfg.validate::<MockError>() {
+            Err(As7331Error::InvalidConfig { field, .. }) => field,
+            other => panic!("unexpected {:?}", other),
+        };
+        let ok = Config::default();
+        assert!(ok.validate::<MockError>().is_ok());
+        assert_eq!(field(Config { gain: 12, ..ok }), "gain");
+        assert_eq!(field(Config { time: 15, ..ok }), "time");
+        assert_eq!(field(Config { cclk: 4, ..ok }), "cclk");
+        let synd = Config {
+            mmode: AS7331_CREG3_MMODE_SYND,
+            edges: 0,
+            ..ok
+        };
+        assert_eq!(field(synd), "edges");
+        let cont = Config {
+            mmode: AS7331_CREG3_MMODE_CONT,
+            break_time: 0,
+            ..ok
+        };
+        assert_eq!(field(cont), "break_time");
+
+        let mut dev = dev(Mock::new());
+        assert!(dev.apply(&cont).is_err());
+        assert!(dev.i2c.writes.is_empty());
+    }
+
+    #[test]
+    fn channel_overflow_needs_a_clipped_result() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[1..4].copy_from_slice(&[0xFFFF, 1000, 0]);
+        assert_eq!(dev.read_channel_validated(Channel::Uva).unwrap(), 0xFFFF);
+        dev.i2c.flags = AS7331_STATUS_ADCOF;
+        assert!(matches!(
+            dev.read_channel_validated(Channel::Uva),
+            Err(As7331Error::Saturated)
+        ));
+        assert_eq!(dev.read_channel_validated(Channel::Uvb).unwrap(), 1000);
+        dev.config.time = AS7331_CREG1_TIME_1;
+        dev.i2c.results[2] = 1024;
+        assert!(matches!(
+            dev.read_channel_validated(Channel::Uvb),
+            Err(As7331Error::Saturated)
+        ));
+        assert_eq!(dev.i2c.reads.last(), Some(&(MeasReg::Status.addr(), 8)));
+    }
+
+    #[test]
+    fn reset_to_defaults_applies_the_baseline() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.set_warmup_samples(0);
+        let mut delay = MockDelay::default();
+        dev.reset_to_defaults(&mut delay).unwrap();
+        assert_eq!(dev.i2c.writes[0], (0, 0x08));
+        assert!(delay.ns >= AS7331_RESET_DELAY_MS as u64 * 1_000_000);
+        let baseline = Config::baseline();
+        assert_eq!(*dev.config(), baseline);
+        assert_eq!(dev.i2c.config[6], pack_creg1(baseline.gain, baseline.time));
+        assert_eq!(dev.i2c.osr, 0x03);
+    }
+
+    #[test]
+    fn nonblocking_temperature_leaves_results_pending() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        assert_eq!(dev.read_temperature_nonblocking().unwrap(), None);
+        dev.i2c.queue.push_back([1838, 1, 2, 3]);
+        let t = dev.read_temperature_nonblocking().unwrap().unwrap();
+        assert!((t - 25.0).abs() < 1e-3);
+        assert!(dev.read_status().unwrap().ndata);
+        assert_eq!(dev.read_all_data().unwrap(), [1838, 1, 2, 3]);
+    }
+
+    #[test]
+    fn read_dispatches_on_measurement_mode() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results = [0x10, 1, 2, 3, 0x3456, 0x0012];
+        match dev.read().unwrap() {
+            ReadResult::Measurement(m) => assert_eq!(m.raw, [0x10, 1, 2, 3]),
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut dev = measuring(AS7331_CREG3_MMODE_SYND);
+        dev.i2c.results = [0x10, 1, 2, 3, 0x3456, 0x0012];
+        match dev.read().unwrap() {
+            ReadResult::Synd {
+                measurement,
+                conversions,
+            } => {
+                assert_eq!(measurement.raw, [0x10, 1, 2, 3]);
+                assert_eq!(conversions, 0x12_3456);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(dev.i2c.reads, [(MeasReg::Temp.addr(), 12)]);
+    }
+
+    #[test]
+    fn checked_temperature_boundaries() {
+        assert!(!convert::temperature_in_range(537));
+        assert!(convert::temperature_in_range(538));
+        assert!(convert::temperature_in_range(3038));
+        assert!(!convert::temperature_in_range(3039));
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[0] = 538;
+        assert!((dev.read_temperature_checked().unwrap() + 40.0).abs() < 1e-3);
+        dev.i2c.results[0] = 3038;
+        assert!((dev.read_temperature_checked().unwrap() - 85.0).abs() < 1e-3);
+        dev.i2c.results[0] = 0;
+        assert!(matches!(
+            dev.read_temperature_checked(),
+            Err(As7331Error::TemperatureOutOfRange(0))
+        ));
+    }
+
+    #[test]
+    fn osr_round_trips_every_field() {
+        for byte in (0..=u8::MAX).filter(|b| b & 0x30 == 0) {
+            assert_eq!(Osr::from_byte(byte).to_byte(), byte);
+        }
+        let mut dev = dev(Mock::new());
+        let osr = dev.read_osr().unwrap();
+        assert_eq!(
+            osr,
+            Osr {
+                dos: AS7331_OSR_DOS_CONFIGURATION,
+                sw_res: false,
+                pd: true,
+                ss: false,
+            }
+        );
+    }
+
+    #[test]
+    fn temperature_burst_fills_up_to_n_samples() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[0] = 1838;
+        let mut out = [0u16; 4];
+        assert_eq!(dev.read_temperature_burst(3, &mut out).unwrap(), 3);
+        assert_eq!(out, [1838, 1838, 1838, 0]);
+        assert_eq!(dev.read_temperature_burst(10, &mut out).unwrap(), 4);
+        assert_eq!(dev.i2c.reads.len(), 7);
+        dev.i2c.fail_reg = Some(MeasReg::Temp.addr());
+        assert!(dev.read_temperature_burst(2, &mut out).is_err());
+    }
+
+    #[test]
+    fn read_selection_reads_runs_of_registers() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[..4].copy_from_slice(&[1838, 1, 2, 3]);
+        dev.set_read_selection(ReadSelection::TEMP | ReadSelection::UVB | ReadSelection::UVC);
+        let ReadResult::Measurement(m) = dev.read().unwrap() else {
+            panic!("not a plain measurement");
+        };
+        assert_eq!(m.raw, [1838, 0, 2, 3]);
+        assert_eq!(m.uva, 0.0);
+        assert!((m.temperature - 25.0).abs() < 1e-3);
+        assert_eq!(dev.i2c.reads, [(1, 2), (3, 4)]);
+
+        dev.set_read_selection(ReadSelection::UVA);
+        let ReadResult::Measurement(m) = dev.read().unwrap() else {
+            panic!("not a plain measurement");
+        };
+        assert!(m.temperature.is_nan());
+        assert_eq!(m.raw, [0, 1, 0, 0]);
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn summary_line_formats_for_small_displays() {
+        let dev = dev(Mock::new());
+        let m = Measurement {
+            raw: [0; 4],
+            temperature: 28.4,
+            uva: 12.34,
+            uvb: 4.5,
+            uvc: 0.2,
+        };
+        assert_eq!(
+            dev.summary_line(&m).as_str(),
+            "UVA:12.3 UVB:4.5 UVC:0.2 T:28C"
+        );
+        let huge = Measurement {
+            uva: 1e30,
+            uvb: 1e30,
+            ..m
+        };
+        let line = dev.summary_line(&huge);
+        assert!(line.starts_with("UVA:1000000"));
+        assert!(!line.contains("T:"));
+    }
+
+    #[test]
+    fn temperature_only_uses_shortest_command_conversion() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.configure_temperature_only().unwrap();
+        assert_eq!(dev.config.mmode, AS7331_CREG3_MMODE_CMD);
+        assert_eq!(dev.config.time, AS7331_CREG1_TIME_1);
+        assert_eq!(dev.i2c.config[6] & 0x0f, AS7331_CREG1_TIME_1);
+        assert_eq!(dev.i2c.osr, 0x03);
+        dev.i2c.queue.push_back([1838, 1, 2, 3]);
+        let t = dev
+            .measure_temperature_only(5, &mut MockDelay::default())
+            .unwrap();
+        assert!((t - 25.0).abs() < 1e-3);
+        assert_eq!(dev.i2c.reads.last(), Some(&(MeasReg::Temp.addr(), 2)));
+    }
+
+    #[test]
+    fn autorange_discards_first_reading_after_gain_change() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        let gain = dev.config.gain;
+        dev.i2c.queue.extend([
+            [0, 0x1000, 0, 0],
+            [0, 0xFFFF, 0xFFFF, 0xFFFF],
+            [0, 0x8000, 0, 0],
+        ]);
+        dev.uv_index_autorange(10, &mut MockDelay::default())
+            .unwrap();
+        assert_eq!(dev.config.gain, gain - 1);
+        assert_eq!(dev.i2c.writes.iter().filter(|w| w.0 == 6).count(), 1);
+        assert!(dev.i2c.queue.is_empty());
+    }
+
+    #[test]
+    fn channel_ratios_use_calibrated_irradiance() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[1..4].copy_from_slice(&[1000, 500, 250]);
+        let lsb = dev.resolution();
+        let r = dev.channel_ratios().unwrap();
+        let close = |a: Option<f32>, b: f32| (a.unwrap() - b).abs() <= b * 1e-5;
+        assert!(close(r.uvb_over_uva, 500.0 * lsb[1] / (1000.0 * lsb[0])));
+        assert!(close(r.uvc_over_uva, 250.0 * lsb[2] / (1000.0 * lsb[0])));
+        assert!(close(r.uvc_over_uvb, 250.0 * lsb[2] / (500.0 * lsb[1])));
+    }
+
+    #[test]
+    fn channel_ratios_are_none_without_uva() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[1..4].copy_from_slice(&[0, 500, 250]);
+        let r = dev.channel_ratios().unwrap();
+        assert_eq!(r.uvb_over_uva, None);
+        assert_eq!(r.uvc_over_uva, None);
+        assert!(r.uvc_over_uvb.is_some());
+    }
+
+    #[test]
+    fn retry_count_recovers_transient_read_failure() {
+        let mut dev = dev(Mock::new());
+        dev.set_retry_count(2);
+        dev.i2c.fail_reads = 2;
+        assert_eq!(dev.get_chip_id(), Ok(0x21));
+        assert_eq!(dev.i2c.reads.len(), 3);
+    }
+
+    #[test]
+    fn retry_count_propagates_persistent_failure() {
+        let mut dev = dev(Mock::new());
+        dev.set_retry_count(2);
+        dev.i2c.fail_reads = 3;
+        assert_eq!(dev.get_chip_id(), Err(MockError));
+        assert_eq!(dev.i2c.reads.len(), 3);
+    }
+
+    #[test]
+    fn set_cclk_changes_only_the_clock_bits() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.config[8] = (AS7331_CREG3_MMODE_CONT << 6) | 0x10 | 0x08;
+        dev.set_cclk(Cclk::Mhz4_096).unwrap();
+        assert_eq!(
+            dev.i2c.config[8],
+            (AS7331_CREG3_MMODE_CONT << 6) | 0x10 | 0x08 | Cclk::Mhz4_096.bits()
+        );
+        assert_eq!(dev.config.cclk, Cclk::Mhz4_096.bits());
+        assert_eq!(dev.i2c.osr, 0x83);
+    }
+
+    #[test]
+    fn health_check_reads_chip_id_in_configuration_mode() {
+        let mut dev = dev(Mock::new());
+        let config = dev.i2c.config;
+        let report = dev.health_check().unwrap();
+        assert_eq!(report.chip_id, Some(0x21));
+        assert_eq!(report.chip_id_valid, Some(true));
+        assert_eq!(report.status, None);
+        assert_eq!(report.temperature, None);
+        assert!(dev.i2c.writes.is_empty());
+        assert_eq!(dev.i2c.config, config);
+    }
+
+    #[test]
+    fn health_check_reads_status_and_temperature_when_measuring() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.flags = AS7331_STATUS_ADCOF;
+        dev.i2c.results[0] = 1838;
+        let report = dev.health_check().unwrap();
+        assert_eq!(report.osr, Osr::from_byte(0x83));
+        assert_eq!(report.status, Some(Status::from_byte(AS7331_STATUS_ADCOF)));
+        assert!((report.temperature.unwrap() - 25.0).abs() < 1e-3);
+        assert_eq!(report.chip_id, None);
+        assert!(dev.i2c.writes.is_empty());
+    }
+
+    #[test]
+    fn normalized_irradiance_is_invariant_to_integration_time() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[1..4].copy_from_slice(&[1000, 2000, 3000]);
+        let before = dev.normalized_irradiance().unwrap();
+        let cfg = Config {
+            time: dev.config.time + 1,
+            ..dev.config
+        };
+        dev.apply(&cfg).unwrap();
+        dev.set_measurement_mode().unwrap();
+        dev.i2c.results[1..4].copy_from_slice(&[2000, 4000, 6000]);
+        let after = dev.normalized_irradiance().unwrap();
+        for (a, b) in before.iter().zip(after) {
+            assert!((a - b).abs() <= a * 1e-5);
+        }
+    }
+
+    #[test]
+    fn last_conversion_time_follows_cclk() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[4] = 0x2000;
+        dev.i2c.results[5] = 0x0001;
+        let count = 0x01_2000 as f32;
+        let ms = dev.last_conversion_time_ms().unwrap();
+        assert!((ms - count / 1024.0).abs() < 1e-3);
+        assert_eq!(dev.i2c.reads.last(), Some(&(MeasReg::OutconvL.addr(), 4)));
+        dev.config.cclk = Cclk::Mhz4_096.bits();
+        let ms = dev.last_conversion_time_ms().unwrap();
+        assert!((ms - count / 4096.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn register_enums_map_to_datasheet_addresses() {
+        let config = [
+            (ConfigReg::Osr, 0x00),
+            (ConfigReg::Agen, 0x02),
+            (ConfigReg::Creg1, 0x06),
+            (ConfigReg::Creg2, 0x07),
+            (ConfigReg::Creg3, 0x08),
+            (ConfigReg::Break, 0x09),
+            (ConfigReg::Edges, 0x0a),
+            (ConfigReg::Optreg, 0x0b),
+        ];
+        for (reg, addr) in config {
+            assert_eq!(reg.addr(), addr);
+            assert_eq!(Register::addr(reg), addr);
+        }
+        let meas = [
+            (MeasReg::Status, 0x00),
+            (MeasReg::Temp, 0x01),
+            (MeasReg::Mres1, 0x02),
+            (MeasReg::Mres2, 0x03),
+            (MeasReg::Mres3, 0x04),
+            (MeasReg::OutconvL, 0x05),
+            (MeasReg::OutconvH, 0x06),
+        ];
+        for (reg, addr) in meas {
+            assert_eq!(reg.addr(), addr);
+            assert_eq!(Register::addr(reg), addr);
+        }
+    }
+
+    #[test]
+    fn pipelined_measure_triggers_before_processing() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.osr = 0x03;
+        dev.i2c
+            .queue
+            .extend([[0, 10, 0, 0], [0, 20, 0, 0], [0, 30, 0, 0]]);
+        let mut seen = Vec::new();
+        dev.pipelined_measure(10, &mut MockDelay::default(), |m| {
+            seen.push(m.raw[1]);
+            seen.len() < 2
+        })
+        .unwrap();
+        assert_eq!(seen, [10, 20]);
+        assert!(dev.i2c.queue.is_empty());
+        let order: String = dev
+            .i2c
+            .ops
+            .iter()
+            .filter_map(|op| match *op {
+                (0, Some(v)) if v & 0x80 != 0 => Some('T'),
+                (r, None) if r == MeasReg::Temp.addr() => Some('R'),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(order, "TRTRT");
+    }
+
+    #[test]
+    fn break_time_reads_back_in_microseconds() {
+        let mut dev = dev(Mock::new());
+        dev.i2c.config[9] = 0x19;
+        assert_eq!(dev.get_break_time_us(), Ok(200));
+        assert_eq!(dev.read_config().unwrap().break_time_us(), 200);
+        dev.i2c.config[8] = (dev.i2c.config[8] & !0x03) | Cclk::Mhz4_096.bits();
+        assert_eq!(dev.get_break_time_us(), Ok(50));
+        assert_eq!(dev.read_config().unwrap().break_time_us(), 50);
+    }
+
+    #[test]
+    fn spectrum_report_is_consistent_with_its_measurement() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[..4].copy_from_slice(&[1838, 1000, 500, 250]);
+        let r = dev.spectrum_report().unwrap();
+        let m = r.measurement;
+        assert_eq!(m.raw, [1838, 1000, 500, 250]);
+        assert!((m.temperature - 25.0).abs() < 1e-3);
+        assert_eq!(r.config, dev.config);
+        assert_eq!(r.ratios, ChannelRatios::from_measurement(&m));
+        let uv = [m.uva, m.uvb, m.uvc];
+        for ((flux, uv), wl) in r.photon_flux.iter().zip(uv).zip(dev.wavelengths_nm) {
+            let per_uw = flux / uv;
+            let expected = wl * AS7331_PHOTONS_PER_UJ_NM;
+            assert!((per_uw - expected).abs() <= expected * 1e-5);
+        }
+        assert!(r.ratios.uvb_over_uva.unwrap() > 0.0);
+    }
+
+    #[test]
+    fn differential_read_is_signed_around_baseline() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[1..4].copy_from_slice(&[1500, 200, 0xFFFF]);
+        assert_eq!(dev.read_channel_differential(Channel::Uva, 1000), Ok(500));
+        assert_eq!(dev.read_channel_differential(Channel::Uvb, 1000), Ok(-800));
+        assert_eq!(dev.read_channel_differential(Channel::Uvc, 0), Ok(0xFFFF));
+        dev.i2c.results[3] = 0;
+        assert_eq!(
+            dev.read_channel_differential(Channel::Uvc, 0xFFFF),
+            Ok(-0xFFFF)
+        );
+    }
+
+    #[test]
+    fn higher_gain_lowers_uncertainty() {
+        let mut dev = dev(Mock::new());
+        dev.config.gain = AS7331_CREG1_GAIN_1;
+        let m = dev.decode([1838, 100, 100, 100]);
+        let coarse = dev.measurement_uncertainty(&m);
+        dev.config.gain = AS7331_CREG1_GAIN_2048;
+        let fine = dev.measurement_uncertainty(&m);
+        for (f, c) in fine.iter().zip(coarse) {
+            assert!(*f < c);
+        }
+    }
+
+    #[test]
+    fn noise_model_adds_to_quantization() {
+        let mut dev = dev(Mock::new());
+        let m = dev.decode([1838, 1000, 1000, 1000]);
+        let quantization = dev.measurement_uncertainty(&m);
+        let lsb = dev.lsb();
+        for (u, l) in quantization.iter().zip(lsb) {
+            assert!((u - 0.5 * l).abs() <= l * 1e-5);
+        }
+        dev.set_noise_model(NoiseModel {
+            counts: [1.5; 3],
+            relative: [0.01; 3],
+        });
+        let noisy = dev.measurement_uncertainty(&m);
+        let value = [m.uva, m.uvb, m.uvc];
+        for i in 0..3 {
+            let expected = 2.0 * lsb[i] + 0.01 * value[i];
+            assert!((noisy[i] - expected).abs() <= expected * 1e-5);
+        }
+    }
+
+    #[test]
+    fn med_fraction_of_uv_index_one() {
+        let uvb = 2.5 / AS7331_ERYTHEMAL_WEIGHT_UVB;
+        assert!((uv_index_from_irradiance(0.0, uvb) - 1.0).abs() < 1e-5);
+        // UV index 1 is 0.025 W/m^2, so 250 J/m^2 take 10000 s
+        assert!((med_fraction(0.0, uvb, 10_000.0) - 1.0).abs() < 1e-4);
+        assert!((med_fraction(0.0, uvb, 2_500.0) - 0.25).abs() < 1e-4);
+        assert_eq!(med_fraction(0.0, uvb, 0.0), 0.0);
+    }
+
+    #[test]
+    fn sun_exposure_uses_current_results() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[..4].copy_from_slice(&[1838, 2000, 1000, 0]);
+        let m = dev.decode([1838, 2000, 1000, 0]);
+        let med = dev.sun_exposure_med(600.0).unwrap();
+        assert_eq!(med, med_fraction(m.uva, m.uvb, 600.0));
+        assert!(med > 0.0);
+    }
+
+    #[test]
+    fn raw_bytes_swap_between_endians() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[..4].copy_from_slice(&[0x0102, 0x0304, 0x0506, 0x0708]);
+        let little = dev.read_all_data_bytes(Endian::Little).unwrap();
+        let big = dev.read_all_data_bytes(Endian::Big).unwrap();
+        assert_eq!(little, [0x02, 0x01, 0x04, 0x03, 0x06, 0x05, 0x08, 0x07]);
+        assert_eq!(big, [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+        for (l, b) in little.chunks_exact(2).zip(big.chunks_exact(2)) {
+            assert_eq!(
+                u16::from_le_bytes([l[0], l[1]]),
+                u16::from_be_bytes([b[0], b[1]])
+            );
+        }
+    }
+
+    #[test]
+    fn mmode_setter_refuses_measurement_mode() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        assert!(matches!(
+            dev.set_measurement_mode_mmode(MeasurementMode::Command),
+            Err(As7331Error::WrongMode)
+        ));
+        assert!(dev.i2c.writes.is_empty());
+        assert_eq!(dev.config.mmode, AS7331_CREG3_MMODE_CONT);
+
+        dev.set_configuration_mode().unwrap();
+        let creg3 = dev.i2c.config[8];
+        dev.set_measurement_mode_mmode(MeasurementMode::Command)
+            .unwrap();
+        assert_eq!(
+            dev.i2c.config[8],
+            creg3 & 0x3f | AS7331_CREG3_MMODE_CMD << 6
+        );
+        assert_eq!(dev.config.mmode, AS7331_CREG3_MMODE_CMD);
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn history_keeps_the_most_recent_results() {
+        let mut rec = HistoryRecorder::<_, 3>::new(measuring(AS7331_CREG3_MMODE_CMD));
+        rec.dev.i2c.queue.extend((1..=5).map(|uva| [0, uva, 0, 0]));
+        for _ in 0..5 {
+            rec.measure_blocking(10, &mut MockDelay::default()).unwrap();
+        }
+        assert_eq!(rec.history().len(), 3);
+        let ordered: Vec<u16> = rec.history_ordered().map(|m| m.raw[1]).collect();
+        assert_eq!(ordered, [3, 4, 5]);
+        rec.clear_history();
+        assert!(rec.history().is_empty());
+    }
+
+    #[test]
+    fn extended_count_is_raw_times_divider() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[1..4].copy_from_slice(&[1000, 0xFFFF, 7]);
+        dev.config.divider = Divider::Off;
+        assert_eq!(dev.read_channel_extended(Channel::Uva), Ok(1000));
+        dev.config.divider = Divider::Div8;
+        assert_eq!(dev.read_channel_extended(Channel::Uva), Ok(8000));
+        assert_eq!(dev.read_channel_extended(Channel::Uvc), Ok(56));
+        dev.config.divider = Divider::Div128;
+        assert_eq!(dev.read_channel_extended(Channel::Uvb), Ok(0xFFFF * 128));
+    }
+
+    #[test]
+    fn measurement_mode_bits_match_legacy_constants() {
+        assert_eq!(MeasurementMode::Continuous.bits(), AS7331_CREG3_MMODE_CONT);
+        assert_eq!(MeasurementMode::Command.bits(), AS7331_CREG3_MMODE_CMD);
+        assert_eq!(MeasurementMode::SynStart.bits(), AS7331_CREG3_MMODE_SYNS);
+        assert_eq!(MeasurementMode::SynStartEnd.bits(), AS7331_CREG3_MMODE_SYND);
+    }
+
+    #[test]
+    fn init_writes_mode_into_creg3() {
+        let mut dev = dev(Mock::new());
+        dev.init(
+            MeasurementMode::SynStartEnd,
+            AS7331_CREG3_CCLK_1024,
+            AS7331_CREG3_SB_ON,
+            0,
+            AS7331_CREG1_GAIN_1,
+            AS7331_CREG1_TIME_1,
+        )
+        .unwrap();
+        assert_eq!(dev.i2c.config[8] >> 6, AS7331_CREG3_MMODE_SYND);
+        assert_eq!(dev.config.mmode, AS7331_CREG3_MMODE_SYND);
+    }
+
+    #[test]
+    fn stalled_stream_yields_stall_error() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.queue.push_back([0, 100, 0, 0]);
+        let mut delay = MockDelay::default();
+        let mut it = dev.measurements(10, &mut delay).stall_timeout_ms(5);
+        assert_eq!(it.next().unwrap().unwrap().raw[1], 100);
+        assert!(matches!(it.next(), Some(Err(As7331Error::Stalled))));
+        assert!(delay.ns >= 5_000_000);
+
+        let mut delay = MockDelay::default();
+        let mut it = dev.measurements(5, &mut delay);
+        assert!(matches!(it.next(), Some(Err(As7331Error::Timeout))));
+    }
+
+    #[test]
+    fn available_settings_cover_every_register_value() {
+        let gains = available_gains();
+        assert_eq!(gains.len(), 12);
+        for (i, g) in gains.iter().enumerate() {
+            assert_eq!(g.factor(), 1 << i);
+            assert_eq!(g.bits(), AS7331_CREG1_GAIN_1 - i as u8);
+        }
+        let times = available_integration_times();
+        assert_eq!(times.len(), 15);
+        for (i, t) in times.iter().enumerate() {
+            assert_eq!(t.ms(), 1 << i);
+            assert_eq!(t.bits(), i as u8);
+        }
+    }
+
+    #[test]
+    fn measurement_complete_uses_notready_in_command_mode() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.busy_polls = 1;
+        dev.i2c.flags = AS7331_STATUS_NDATA;
+        assert_eq!(dev.measurement_complete().ok(), Some(false));
+        dev.i2c.flags = 0;
+        assert_eq!(dev.measurement_complete().ok(), Some(true));
+    }
+
+    #[test]
+    fn measurement_complete_uses_ndata_in_other_modes() {
+        for mmode in [
+            AS7331_CREG3_MMODE_CONT,
+            AS7331_CREG3_MMODE_SYNS,
+            AS7331_CREG3_MMODE_SYND,
+        ] {
+            let mut dev = measuring(mmode);
+            assert_eq!(dev.measurement_complete().ok(), Some(false));
+            dev.i2c.queue.push_back([0; 4]);
+            assert_eq!(dev.measurement_complete().ok(), Some(true));
+        }
+    }
+
+    #[test]
+    fn config_matches_detects_register_changes() {
+        let mut dev = dev(Mock::new());
+        let cfg = Config {
+            gain: AS7331_CREG1_GAIN_64,
+            ..dev.read_config().unwrap()
+        };
+        dev.apply(&cfg).unwrap();
+        assert!(matches!(dev.config_matches(&cfg), Ok(true)));
+        dev.i2c.config[6] ^= 0x10;
+        assert!(matches!(dev.config_matches(&cfg), Ok(false)));
+
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        assert!(matches!(
+            dev.config_matches(&cfg),
+            Err(As7331Error::WrongMode)
+        ));
+    }
+
+    #[test]
+    fn detailed_read_keeps_uv_when_temperature_fails() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[..4].copy_from_slice(&[1838, 100, 200, 300]);
+        dev.i2c.fail_reg = Some(MeasReg::Temp.addr());
+        let r = dev.read_detailed();
+        assert!(matches!(r.temperature, Err(As7331Error::I2c(MockError))));
+        let lsb = dev.lsb();
+        let cal = dev.calibration.factors(None);
+        assert_eq!(r.uva.ok(), Some(100.0 * lsb[0] * cal[0]));
+        assert_eq!(r.uvb.ok(), Some(200.0 * lsb[1] * cal[1]));
+        assert_eq!(r.uvc.ok(), Some(300.0 * lsb[2] * cal[2]));
+
+        dev.i2c.fail_reg = Some(MeasReg::Mres2.addr());
+        let r = dev.read_detailed();
+        assert!((r.temperature.unwrap() - 25.0).abs() < 1e-3);
+        assert!(r.uva.is_ok() && r.uvb.is_err() && r.uvc.is_ok());
+    }
+
+    #[test]
+    fn register_str_parses_a_dump() {
+        let cfg = Config::from_register_str("CREG1=0x76 creg3=0x40\tBREAK=16").unwrap();
+        assert_eq!((cfg.gain, cfg.time), (7, 6));
+        assert_eq!(cfg.mmode, AS7331_CREG3_MMODE_CMD);
+        assert_eq!(cfg.sb, 0);
+        assert_eq!(cfg.break_time, 16);
+        assert_eq!(cfg.divider, Divider::from_creg2(AS7331_CONFIG_DEFAULTS[1]));
+        assert_eq!(cfg.edges, AS7331_CONFIG_DEFAULTS[4]);
+        assert_eq!(
+            Config::from_register_str("").unwrap(),
+            Config::from_registers(AS7331_CONFIG_DEFAULTS[..5].try_into().unwrap())
+        );
+    }
+
+    #[test]
+    fn register_str_rejects_malformed_entries() {
+        assert_eq!(
+            Config::from_register_str("CREG1 0x76"),
+            Err(ParseError::Syntax)
+        );
+        assert_eq!(
+            Config::from_register_str("OSR=0x02"),
+            Err(ParseError::UnknownRegister)
+        );
+        assert_eq!(
+            Config::from_register_str("CREG1=0x176"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(
+            Config::from_register_str("BREAK=ten"),
+            Err(ParseError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn verify_config_applied_retries_ignored_apply() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        let creg1 = dev.i2c.config[6];
+        let cfg = Config {
+            gain: AS7331_CREG1_GAIN_64,
+            time: AS7331_CREG1_TIME_1,
+            break_time: 0x20,
+            ..dev.config
+        };
+        dev.apply(&cfg).unwrap();
+        assert_eq!(dev.i2c.config[6], creg1);
+        dev.verify_config_applied(&cfg).unwrap();
+        assert_eq!(
+            dev.i2c.config[6],
+            pack_creg1(AS7331_CREG1_GAIN_64, AS7331_CREG1_TIME_1)
+        );
+        assert_eq!(dev.i2c.config[9], 0x20);
+        assert_eq!(dev.i2c.osr & 0x07, AS7331_OSR_DOS_CONFIGURATION);
+        assert!(matches!(dev.config_matches(&cfg), Ok(true)));
+    }
+
+    #[test]
+    fn saturation_tracker_counts_only_the_window() {
+        let mut t = SaturationTracker::new(4);
+        assert_eq!(t.saturation_rate(), 0.0);
+        t.record(true);
+        t.record(false);
+        assert_eq!(t.saturation_rate(), 0.5);
+        for saturated in [true, true, false] {
+            t.record(saturated);
+        }
+        // the first saturated read has left the window of 4
+        assert_eq!(t.saturation_rate(), 0.5);
+        t.reset();
+        assert_eq!(t.saturation_rate(), 0.0);
+        assert_eq!(SaturationTracker::new(0), SaturationTracker::new(1));
+    }
+
+    #[test]
+    fn validated_reads_feed_the_saturation_rate() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.config.time = AS7331_CREG1_TIME_1;
+        dev.set_saturation_window(4);
+        dev.i2c.flags = AS7331_STATUS_ADCOF;
+        dev.i2c.results[1] = 1024;
+        for _ in 0..3 {
+            assert!(matches!(
+                dev.read_channel_validated(Channel::Uva),
+                Err(As7331Error::Saturated)
+            ));
+        }
+        dev.i2c.flags = 0;
+        assert_eq!(dev.read_channel_validated(Channel::Uva).ok(), Some(1024));
+        assert_eq!(dev.saturation_rate(), 0.75);
+    }
+
+    #[test]
+    fn uv_only_read_is_one_six_byte_transfer() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[..4].copy_from_slice(&[0x1111, 0x0102, 0x0304, 0xfffe]);
+        assert_eq!(dev.read_uv_only(), Ok([0x0102, 0x0304, 0xfffe]));
+        assert_eq!(dev.i2c.reads, [(MeasReg::Mres1.addr(), 6)]);
+    }
+
+    #[test]
+    fn identity_correction_is_a_no_op() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[..4].copy_from_slice(&[1838, 1000, 2000, 3000]);
+        let m = dev.decode([1838, 1000, 2000, 3000]);
+        assert_eq!(dev.read_uv_corrected().unwrap(), [m.uva, m.uvb, m.uvc]);
+        dev.set_correction_matrix([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+        assert_eq!(dev.read_uv_corrected().unwrap(), [m.uva, m.uvb, m.uvc]);
+    }
+
+    #[test]
+    fn correction_matrix_removes_cross_talk() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[..4].copy_from_slice(&[1838, 1000, 2000, 3000]);
+        let m = dev.decode([1838, 1000, 2000, 3000]);
+        dev.set_correction_matrix([[1.0, -0.1, 0.0], [0.0, 1.0, -0.2], [0.5, 0.0, 2.0]]);
+        let uv = dev.read_uv_corrected().unwrap();
+        let expected = [
+            m.uva - 0.1 * m.uvb,
+            m.uvb - 0.2 * m.uvc,
+            0.5 * m.uva + 2.0 * m.uvc,
+        ];
+        for (u, e) in uv.iter().zip(expected) {
+            assert!((u - e).abs() <= e.abs() * 1e-5);
+        }
+    }
+
+    #[test]
+    fn temperature_units_follow_celsius() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[0] = 1838;
+        let c = temperature_celsius(1838);
+        assert_eq!(dev.read_temperature_fahrenheit(), Ok(c * 1.8 + 32.0));
+        assert_eq!(dev.read_temperature_kelvin(), Ok(c + 273.15));
+        assert!((dev.read_temperature_fahrenheit().unwrap() - 77.0).abs() < 1e-2);
+        assert!((dev.read_temperature_kelvin().unwrap() - 298.15).abs() < 1e-2);
+    }
+
+    #[test]
+    fn trigger_then_poll_until_complete() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.osr = 0x03;
+        dev.i2c.busy_polls = 2;
+        dev.i2c.queue.push_back([1838, 10, 20, 30]);
+        let token = dev.trigger().unwrap();
+        assert_eq!(dev.i2c.osr, 0x83);
+        for _ in 0..2 {
+            assert!(matches!(
+                dev.try_complete(&token),
+                Err(nb::Error::WouldBlock)
+            ));
+        }
+        let m = dev.try_complete(&token).unwrap();
+        assert_eq!(m.raw, [1838, 10, 20, 30]);
+        assert_eq!(dev.i2c.osr, 0x03);
+    }
+
+    #[test]
+    fn result_registers_are_contiguous() {
+        let addrs = AS7331_RESULT_REGS.map(MeasReg::addr);
+        assert_eq!(addrs, [0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(MeasReg::Status.addr() + 1, MeasReg::Temp.addr());
+
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[..4].copy_from_slice(&[0x0a0b, 0x0c0d, 0x0e0f, 0x1011]);
+        assert_eq!(dev.read_all_data(), Ok([0x0a0b, 0x0c0d, 0x0e0f, 0x1011]));
+        assert_eq!(dev.i2c.reads, [(MeasReg::Temp.addr(), 8)]);
+    }
+
+    #[test]
+    fn low_latency_preset_writes_fastest_settings() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        let gain = dev.config.gain;
+        dev.configure_low_latency().unwrap();
+        let writes = &dev.i2c.writes;
+        assert!(writes.contains(&(6, pack_creg1(gain, AS7331_CREG1_TIME_1))));
+        assert!(writes.contains(&(8, AS7331_CREG3_MMODE_CMD << 6 | AS7331_CREG3_CCLK_8192)));
+        assert!(writes.contains(&(9, 0)));
+        assert_eq!(writes.last(), Some(&(0, AS7331_OSR_DOS_MEASUREMENT)));
+        assert_eq!(dev.i2c.osr, AS7331_OSR_DOS_MEASUREMENT);
+        assert_eq!(dev.config.gain, gain);
+        assert_eq!(dev.measurement_duration_ms(), 0.125);
+    }
+
+    #[test]
+    fn full_precision_adds_the_mean_of_the_dropped_bits() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.i2c.results[1..4].copy_from_slice(&[1000, 50, 0]);
+        dev.config.divider = Divider::Off;
+        assert_eq!(
+            dev.read_channel_full_precision(Channel::Uva).ok(),
+            Some(1000.0)
+        );
+        dev.config.divider = Divider::Div4;
+        assert_eq!(
+            dev.read_channel_full_precision(Channel::Uva).ok(),
+            Some(4001.5)
+        );
+        dev.config.divider = Divider::Div16;
+        dev.config.time = AS7331_CREG1_TIME_1;
+        assert_eq!(
+            dev.read_channel_full_precision(Channel::Uvb).ok(),
+            Some(807.5)
+        );
+    }
+
+    #[test]
+    fn full_precision_keeps_zero_and_full_scale() {
+        let mut dev = measuring(AS7331_CREG3_MMODE_CMD);
+        dev.config.divider = Divider::Div16;
+        dev.config.time = AS7331_CREG1_TIME_1;
+        let full_scale = dev.full_scale_counts();
+        dev.i2c.results[1..4].copy_from_slice(&[0, full_scale, 0xFFFF]);
+        assert_eq!(
+            dev.read_channel_full_precision(Channel::Uva).ok(),
+            Some(0.0)
+        );
+        assert_eq!(
+            dev.read_channel_full_precision(Channel::Uvb).ok(),
+            Some(full_scale as f32 * 16.0)
+        );
+        assert_eq!(
+            dev.read_channel_full_precision(Channel::Uvc).ok(),
+            Some(0xFFFF as f32 * 16.0)
+        );
+    }
+
+    #[test]
+    fn syn_edge_polarity_accepts_only_falling_edges() {
+        let mut dev = dev(Mock::new());
+        assert!(dev.set_syn_edge_polarity(false).is_ok());
+        assert!(matches!(
+            dev.set_syn_edge_polarity(true),
+            Err(As7331Error::InvalidConfig {
+                field: "syn_edge_polarity",
+                value: 1
+            })
+        ));
+        assert!(dev.i2c.writes.is_empty() && dev.i2c.reads.is_empty());
+    }
+
+    #[test]
+    fn standby_switch_stays_in_configuration_mode() {
+        let mut dev = dev(Mock::new());
+        assert!(!dev.enter_standby().unwrap());
+        assert_eq!(
+            dev.i2c.config[8] & AS7331_CREG3_SB_MASK,
+            AS7331_CREG3_SB_MASK
+        );
+        assert_eq!(dev.config.sb, AS7331_CREG3_SB_ON);
+        assert_eq!(dev.i2c.osr & 0x07, AS7331_OSR_DOS_CONFIGURATION);
+        assert!(!dev.exit_standby().unwrap());
+        assert_eq!(dev.i2c.config[8] & AS7331_CREG3_SB_MASK, 0);
+        assert_eq!(dev.i2c.osr & 0x07, AS7331_OSR_DOS_CONFIGURATION);
+        assert!(!dev.i2c.writes.iter().any(|w| w.0 == 0));
+    }
+
+    #[test]
+    fn bus_healthy_reports_a_failed_probe() {
+        let mut dev = dev(Mock::new());
+        assert!(dev.bus_healthy());
+        dev.i2c.fail_reads = 1;
+        assert!(!dev.bus_healthy());
+        assert_eq!(dev.i2c.reads, [(0, 1), (0, 1)]);
+        assert!(dev.i2c.writes.is_empty());
+    }
 
-        self.i2c_write_cmd(AS7331_OSR, data[0] | 0x83)
+    #[cfg(feature = "uom")]
+    #[test]
+    fn uom_quantities_convert_to_base_units() {
+        use uom::si::heat_flux_density::watt_per_square_meter;
+        use uom::si::thermodynamic_temperature::kelvin;
+        let mut dev = measuring(AS7331_CREG3_MMODE_CONT);
+        dev.i2c.results[..4].copy_from_slice(&[1838, 1000, 2000, 3000]);
+        let si = dev.read_si().unwrap();
+        let q = dev.read_measurement_uom().unwrap();
+        let close = |a: f32, b: f32| (a - b).abs() <= b.abs() * 1e-5;
+        assert!(close(q.uva.get::<watt_per_square_meter>(), si.uva_w_m2));
+        assert!(close(q.uvb.get::<watt_per_square_meter>(), si.uvb_w_m2));
+        assert!(close(q.uvc.get::<watt_per_square_meter>(), si.uvc_w_m2));
+        assert!(close(
+            q.temperature.get::<kelvin>(),
+            si.temperature_c + 273.15
+        ));
     }
 }
@@ -0,0 +1,214 @@
+//! Unit conversions of the AS7331 results, independent of the bus.
+//!
+//! Gain, integration time and clock are the register codes of CREG1 and
+//! CREG3 as stored in [`Config`].
+
+use crate::as7331::{Config, Divider};
+use core::ops::RangeInclusive;
+
+// Irradiance per count in nW/cm^2 at GAIN=1, 1024 ms integration time
+const AS7331_LSB_UVA: f32 = 304.69;
+const AS7331_LSB_UVB: f32 = 398.44;
+const AS7331_LSB_UVC: f32 = 191.41;
+// Same figures in 1/100 nW/cm^2 for integer conversion
+const AS7331_LSB_CENTI: [u64; 3] = [30469, 39844, 19141];
+
+// Temperature conversion: T = raw * 0.05 - 66.9 (Celsius)
+const AS7331_TEMP_LSB: f32 = 0.05;
+const AS7331_TEMP_OFFSET: f32 = 66.9;
+const AS7331_TEMP_LSB_MILLI: i32 = 50;
+const AS7331_TEMP_OFFSET_MILLI: i32 = 66900;
+
+/// TEMP results inside the operating range of -40 to 85 Celsius, where the
+/// linear temperature formula holds.
+pub const TEMP_RAW_VALID: RangeInclusive<u16> = 538..=3038;
+
+/// Die temperature in Celsius of a TEMP result.
+pub fn temperature_celsius(raw: u16) -> f32 {
+    raw as f32 * AS7331_TEMP_LSB - AS7331_TEMP_OFFSET
+}
+
+/// Celsius to Fahrenheit, F = C * 9/5 + 32.
+pub fn celsius_to_fahrenheit(celsius: f32) -> f32 {
+    celsius * 1.8 + 32.0
+}
+
+/// Celsius to Kelvin, K = C + 273.15.
+pub fn celsius_to_kelvin(celsius: f32) -> f32 {
+    celsius + 273.15
+}
+
+/// Whether a TEMP result is inside [`TEMP_RAW_VALID`].
+pub fn temperature_in_range(raw: u16) -> bool {
+    TEMP_RAW_VALID.contains(&raw)
+}
+
+/// Die temperature in milli-Celsius of a TEMP result, without floating point.
+pub fn temperature_milli_celsius(raw: u16) -> i32 {
+    raw as i32 * AS7331_TEMP_LSB_MILLI - AS7331_TEMP_OFFSET_MILLI
+}
+
+/// Integration time in ms for a CREG1 TIME and CREG3 CCLK setting.
+///
+/// A conversion takes 1024 * 2^TIME clock cycles of 1.024 MHz * 2^CCLK.
+pub fn integration_time_ms(time: u8, cclk: u8) -> f32 {
+    (1u32 << time.min(14)) as f32 / (1u32 << cclk.min(3)) as f32
+}
+
+/// Pause in us for a BREAK value at a CREG3 CCLK setting.
+///
+/// BREAK counts steps of 8 us at 1.024 MHz, which get shorter with a faster
+/// clock.
+pub fn break_time_us(break_time: u8, cclk: u8) -> u32 {
+    (break_time as u32 * 8) >> cclk.min(3)
+}
+
+/// Duration in ms of `count` OUTCONV clock cycles at a CREG3 CCLK setting.
+pub fn outconv_to_ms(count: u32, cclk: u8) -> f32 {
+    count as f32 / (1024u32 << cclk.min(3)) as f32
+}
+
+/// Irradiance per count in uW/cm^2 for UVA, UVB, UVC, without the divider.
+pub fn lsb(gain: u8, time: u8, cclk: u8) -> [f32; 3] {
+    let gain = (1u32 << (11 - gain.min(11))) as f32;
+    let scale = gain * (integration_time_ms(time, cclk) / 1024.0) * 1000.0;
+    [
+        AS7331_LSB_UVA / scale,
+        AS7331_LSB_UVB / scale,
+        AS7331_LSB_UVC / scale,
+    ]
+}
+
+/// Irradiance in uW/cm^2 of one count for UVA, UVB, UVC at `cfg`, including
+/// the divider.
+pub fn resolution(cfg: &Config) -> [f32; 3] {
+    let lsb = lsb(cfg.gain, cfg.time, cfg.cclk);
+    let div = cfg.divider.factor() as f32;
+    [lsb[0] * div, lsb[1] * div, lsb[2] * div]
+}
+
+/// Highest result count at a CREG1 TIME setting and divider.
+///
+/// A conversion counts at most 1024 * 2^TIME clock cycles, so below TIME = 6
+/// the results cannot reach 0xFFFF. The divider scales the count down before
+/// it is clipped to 16 bits.
+pub fn full_scale_counts(time: u8, divider: Divider) -> u16 {
+    let counts = (1024u32 << time.min(14)) >> divider.shift();
+    counts.min(u16::MAX as u32) as u16
+}
+
+/// Irradiance in uW/cm^2 at a `full_scale` result count, see
+/// [`full_scale_counts`], for a per-count `lsb`.
+pub fn full_scale_range(lsb: [f32; 3], full_scale: u16) -> [f32; 3] {
+    let max = full_scale as f32;
+    [lsb[0] * max, lsb[1] * max, lsb[2] * max]
+}
+
+/// Irradiance in uW/cm^2 of UVA, UVB, UVC counts.
+pub fn counts_to_irradiance(counts: [u16; 3], lsb: [f32; 3]) -> [f32; 3] {
+    [
+        counts[0] as f32 * lsb[0],
+        counts[1] as f32 * lsb[1],
+        counts[2] as f32 * lsb[2],
+    ]
+}
+
+/// Irradiance in uW/cm^2 of UVA, UVB, UVC counts taken at `cfg`.
+///
+/// Raw counts scale with gain, integration time, clock and divider and are
+/// not comparable across settings; the irradiance divides all of them out,
+/// so a series stays continuous when the integration time changes.
+pub fn normalized_irradiance(counts: [u16; 3], cfg: &Config) -> [f32; 3] {
+    counts_to_irradiance(counts, resolution(cfg))
+}
+
+/// Counts expected for an irradiance in uW/cm^2, not clipped to 16 bits.
+pub fn irradiance_to_counts(irradiance: [f32; 3], lsb: [f32; 3]) -> [f32; 3] {
+    [
+        irradiance[0] / lsb[0],
+        irradiance[1] / lsb[1],
+        irradiance[2] / lsb[2],
+    ]
+}
+
+/// Irradiance in nW/cm^2 of UVA, UVB, UVC counts at `cfg` with integer
+/// arithmetic only, saturating at `u32::MAX`.
+pub fn counts_to_nw_fixed(counts: [u16; 3], cfg: &Config) -> [u32; 3] {
+    // nW/cm^2 = counts * LSB * 2^(CCLK + GAIN + DIV - 1 - TIME), see lsb()
+    let shift = cfg.cclk.min(3) as i32 + cfg.gain.min(11) as i32 + cfg.divider.shift() as i32
+        - 1
+        - cfg.time.min(14) as i32;
+    let scale = |counts: u16, lsb_centi: u64| -> u32 {
+        let value = counts as u64 * lsb_centi;
+        let nw = if shift >= 0 {
+            (value << shift) / 100
+        } else {
+            value / (100 << -shift)
+        };
+        nw.min(u32::MAX as u64) as u32
+    };
+    [
+        scale(counts[0], AS7331_LSB_CENTI[0]),
+        scale(counts[1], AS7331_LSB_CENTI[1]),
+        scale(counts[2], AS7331_LSB_CENTI[2]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_scale_follows_integration_time() {
+        assert_eq!(full_scale_counts(0, Divider::Off), 1024);
+        assert_eq!(full_scale_counts(5, Divider::Off), 32768);
+        assert_eq!(full_scale_counts(6, Divider::Off), 0xFFFF);
+        assert_eq!(full_scale_counts(14, Divider::Off), 0xFFFF);
+        assert_eq!(full_scale_counts(5, Divider::Div2), 16384);
+        assert_eq!(full_scale_counts(14, Divider::Div256), 0xFFFF);
+        assert_eq!(
+            full_scale_range([1.0, 2.0, 0.5], 1024),
+            [1024.0, 2048.0, 512.0]
+        );
+    }
+
+    #[test]
+    fn temperature_formula() {
+        assert!((temperature_celsius(1338) - 0.0).abs() < 1e-3);
+        assert_eq!(temperature_milli_celsius(1338), 0);
+        assert_eq!(temperature_milli_celsius(1838), 25_000);
+        assert!((celsius_to_fahrenheit(100.0) - 212.0).abs() < 1e-3);
+        assert!((celsius_to_kelvin(0.0) - 273.15).abs() < 1e-3);
+    }
+
+    #[test]
+    fn timing_conversions() {
+        assert_eq!(integration_time_ms(6, 0), 64.0);
+        assert_eq!(integration_time_ms(0, 3), 0.125);
+        assert_eq!(break_time_us(0x19, 0), 200);
+        assert_eq!(break_time_us(0x19, 2), 50);
+        assert_eq!(outconv_to_ms(65536, 0), 64.0);
+        assert_eq!(outconv_to_ms(65536, 1), 32.0);
+    }
+
+    #[test]
+    fn irradiance_conversions_invert() {
+        let cfg = Config::default();
+        let lsb = resolution(&cfg);
+        let uw = counts_to_irradiance([100, 2000, 30000], lsb);
+        let counts = irradiance_to_counts(uw, lsb);
+        for (c, e) in counts.iter().zip([100.0, 2000.0, 30000.0]) {
+            assert!((c - e).abs() < 1e-2);
+        }
+        assert_eq!(normalized_irradiance([100, 2000, 30000], &cfg), uw);
+        let nw = counts_to_nw_fixed([100, 2000, 30000], &cfg);
+        for (n, u) in nw.iter().zip(uw) {
+            assert!((*n as f32 / (u * 1000.0) - 1.0).abs() < 1e-4);
+        }
+        let div4 = Config {
+            divider: Divider::Div4,
+            ..cfg
+        };
+        assert_eq!(resolution(&div4)[1], lsb[1] * 4.0);
+    }
+}
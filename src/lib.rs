@@ -14,6 +14,7 @@
 //!
 //!
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 pub mod as7331;
+pub mod convert;
 pub use as7331::As7331;